@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+use crate::core::EngineConfig;
+use crate::game::input::{InputMapRes, INPUT_MAP_PATH};
+
+pub const SESSION_CONFIG_PATH: &str = "session.ron";
+
+// Session configuration, loaded from a RON file and overridden by the command line
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub engine: EngineConfig,
+    pub network: NetworkConfig,
+    // Falls back to the input map file when missing
+    pub input: Option<InputMapRes>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    // Runs every player locally without a socket, no prediction and no input delay
+    pub offline: bool,
+    pub port: Option<u16>,
+    // Either local or the address of a remote player, in handle order
+    pub players: Vec<String>,
+    pub input_delay: usize,
+    pub max_prediction_window: usize,
+    // Only P2P sessions report ggrs events, synctest and offline sessions have none
+    pub print_events: bool,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            offline: false,
+            port: None,
+            players: Vec::new(),
+            input_delay: 2,
+            max_prediction_window: 12,
+            print_events: true,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)?;
+
+        ron::from_str(&config)
+            .map_err(|error| format!("Invalid session config {}: {}", path.display(), error).into())
+    }
+
+    // Only a missing file falls back to the default configuration, an invalid one is an error
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let pretty = ron::ser::PrettyConfig::default();
+        fs::write(path, ron::ser::to_string_pretty(self, pretty)?)?;
+        Ok(())
+    }
+
+    // Validated wherever it comes from, the session config, the input map file or the defaults
+    pub fn input_map(&self) -> Result<InputMapRes, Box<dyn Error>> {
+        let input_map = match &self.input {
+            Some(input) => input.clone(),
+            None => InputMapRes::load_or_default(INPUT_MAP_PATH)?,
+        };
+
+        validate_input_map(&input_map)?;
+        self.validate_local_devices(&input_map)?;
+        Ok(input_map)
+    }
+
+    // Devices past the local players would silently go unused
+    fn validate_local_devices(&self, input_map: &InputMapRes) -> Result<(), ConfigError> {
+        let local_players = self
+            .network
+            .players
+            .iter()
+            .filter(|player_address| *player_address == "local")
+            .count();
+
+        if input_map.local_devices.len() > local_players {
+            return Err(ConfigError::new(
+                "input.local_devices",
+                format!(
+                    "{} devices given for {} local players",
+                    input_map.local_devices.len(),
+                    local_players
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let engine = &self.engine;
+        let network = &self.network;
+
+        if engine.window_width <= 0.0 {
+            return Err(ConfigError::new("engine.window_width", "must be positive"));
+        }
+        if engine.window_height <= 0.0 {
+            return Err(ConfigError::new("engine.window_height", "must be positive"));
+        }
+        if engine.update_frequency == 0 {
+            return Err(ConfigError::new(
+                "engine.update_frequency",
+                "must be positive",
+            ));
+        }
+
+        if network.players.is_empty() {
+            let message = if network.offline {
+                "at least one local player is required offline, e.g. --players local local"
+            } else {
+                "at least one player is required"
+            };
+            return Err(ConfigError::new("network.players", message));
+        }
+        for (player_handle, player_address) in network.players.iter().enumerate() {
+            let field = format!("network.players[{}]", player_handle);
+
+            if player_address == "local" {
+                continue;
+            }
+            if network.offline {
+                return Err(ConfigError::new(
+                    field,
+                    "remote players are not allowed offline",
+                ));
+            }
+            if player_address.parse::<SocketAddr>().is_err() {
+                return Err(ConfigError::new(
+                    field,
+                    format!("{} is neither local nor a socket address", player_address),
+                ));
+            }
+        }
+        if !network.offline {
+            if network.port.is_none() {
+                return Err(ConfigError::new(
+                    "network.port",
+                    "is required unless network.offline is set",
+                ));
+            }
+            if network.max_prediction_window == 0 {
+                return Err(ConfigError::new(
+                    "network.max_prediction_window",
+                    "must be positive",
+                ));
+            }
+        }
+
+        if let Some(input) = &self.input {
+            validate_input_map(input)?;
+            self.validate_local_devices(input)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_input_map(input_map: &InputMapRes) -> Result<(), ConfigError> {
+    input_map
+        .validate()
+        .map_err(|error| ConfigError::new(format!("input.{}", error.field), error.message))
+}
+
+// Configuration error
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct ConfigError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid {}: {}", self.field, self.message)
+    }
+}
+
+impl Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::SessionConfig;
+    use crate::game::input::{InputDevice, InputMapRes};
+
+    fn config() -> SessionConfig {
+        let mut config = SessionConfig::default();
+        config.network.port = Some(7000);
+        config.network.players = vec!["local".to_string(), "127.0.0.1:7001".to_string()];
+        config
+    }
+
+    #[test]
+    fn validation() {
+        assert!(config().validate().is_ok());
+
+        let mut invalid = config();
+        invalid.network.players[1] = "nowhere".to_string();
+        assert!(invalid.validate().unwrap_err().field == "network.players[1]");
+
+        let mut invalid = config();
+        invalid.network.offline = true;
+        assert!(invalid.validate().unwrap_err().field == "network.players[1]");
+
+        let mut invalid = SessionConfig::default();
+        invalid.network.offline = true;
+        assert!(invalid.validate().unwrap_err().field == "network.players");
+
+        let mut invalid = config();
+        invalid.network.port = None;
+        assert!(invalid.validate().unwrap_err().field == "network.port");
+
+        let mut invalid = config();
+        invalid.engine.update_frequency = 0;
+        assert!(invalid.validate().unwrap_err().field == "engine.update_frequency");
+
+        let mut invalid = config();
+        invalid.input = Some(InputMapRes {
+            stick_deadzone: 1.5,
+            ..Default::default()
+        });
+        assert!(invalid.validate().unwrap_err().field == "input.stick_deadzone");
+        assert!(invalid
+            .input_map()
+            .unwrap_err()
+            .to_string()
+            .contains("input.stick_deadzone"));
+
+        let mut invalid = config();
+        invalid.input = Some(InputMapRes {
+            local_devices: vec![InputDevice::KeyboardA, InputDevice::KeyboardB],
+            ..Default::default()
+        });
+        assert!(invalid.validate().unwrap_err().field == "input.local_devices");
+        assert!(invalid
+            .input_map()
+            .unwrap_err()
+            .to_string()
+            .contains("input.local_devices"));
+    }
+
+    #[test]
+    fn parsing() {
+        let config: SessionConfig =
+            ron::from_str("(network: (offline: true, players: [\"local\", \"local\"]))").unwrap();
+
+        assert!(config.network.offline);
+        assert!(config.network.input_delay == 2);
+        assert!(config.engine.update_frequency == 60);
+        assert!(config.validate().is_ok());
+
+        let error = ron::from_str::<SessionConfig>("(engine: (window_widht: 800))").unwrap_err();
+        assert!(error.to_string().contains("window_widht"));
+    }
+}
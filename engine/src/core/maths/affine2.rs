@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Mul;
+
+use crate::core::maths::{Angle, Number, Vector2};
+
+// 2D affine transform, the first two columns of a 3x3 matrix hold the linear part and the last
+// one the translation
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Affine2 {
+    pub x_axis: Vector2,
+    pub y_axis: Vector2,
+    pub translation: Vector2,
+}
+
+impl Affine2 {
+    pub const IDENTITY: Affine2 = Affine2 {
+        x_axis: Vector2::RIGHT,
+        y_axis: Vector2::UP,
+        translation: Vector2::ZERO,
+    };
+
+    pub fn from_translation(translation: Vector2) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale_rotation_translation(
+        scale: Vector2,
+        rotation: Angle,
+        translation: Vector2,
+    ) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+
+        Self {
+            x_axis: Vector2 { x: cos, y: sin } * scale.x,
+            y_axis: Vector2 { x: -sin, y: cos } * scale.y,
+            translation,
+        }
+    }
+
+    pub fn determinant(&self) -> Number {
+        self.x_axis.cross(self.y_axis)
+    }
+
+    pub fn transform_vector(&self, vector: Vector2) -> Vector2 {
+        self.x_axis * vector.x + self.y_axis * vector.y
+    }
+
+    pub fn transform_point(&self, point: Vector2) -> Vector2 {
+        self.transform_vector(point) + self.translation
+    }
+
+    // Returns None when the transform collapses an axis and cannot be inverted
+    pub fn inverse(&self) -> Option<Affine2> {
+        let determinant = self.determinant();
+
+        if determinant == Number::ZERO {
+            return None;
+        }
+
+        let x_axis = Vector2 {
+            x: self.y_axis.y,
+            y: -self.x_axis.y,
+        } / determinant;
+        let y_axis = Vector2 {
+            x: -self.y_axis.x,
+            y: self.x_axis.x,
+        } / determinant;
+
+        Some(Affine2 {
+            x_axis,
+            y_axis,
+            translation: -(x_axis * self.translation.x + y_axis * self.translation.y),
+        })
+    }
+}
+
+impl Mul for Affine2 {
+    type Output = Affine2;
+
+    fn mul(self, rhs: Affine2) -> Affine2 {
+        Affine2 {
+            x_axis: self.transform_vector(rhs.x_axis),
+            y_axis: self.transform_vector(rhs.y_axis),
+            translation: self.transform_point(rhs.translation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::{Affine2, Angle, Number, Vector2};
+
+    #[test]
+    fn transforms() {
+        let affine = Affine2::from_scale_rotation_translation(
+            Vector2::new(2, 2),
+            Angle::from_radians(Number::FRAC_PI_2),
+            Vector2::new(10, 0),
+        );
+
+        assert!(affine.transform_point(Vector2::RIGHT) == Vector2::new(10, 2));
+        assert!(affine.transform_vector(Vector2::UP) == Vector2::new(-2, 0));
+        assert!(affine.determinant() == Number::from(4));
+        assert!(Affine2::IDENTITY * affine == affine);
+    }
+
+    #[test]
+    fn inverse() {
+        let affine = Affine2::from_scale_rotation_translation(
+            Vector2::new(2, 4),
+            Angle::ZERO,
+            Vector2::new(3, -1),
+        );
+        let inverse = affine.inverse().unwrap();
+
+        assert!(inverse.transform_point(Vector2::new(5, 3)) == Vector2::ONE);
+        assert!(affine * inverse == Affine2::IDENTITY);
+        assert!(
+            Affine2::from_scale_rotation_translation(Vector2::ZERO, Angle::ZERO, Vector2::ONE)
+                .inverse()
+                .is_none()
+        );
+    }
+}
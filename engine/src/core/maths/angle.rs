@@ -0,0 +1,203 @@
+use bevy::reflect::{FromReflect, Reflect};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+use crate::core::maths::{Number, Vector2};
+
+// Angle in radians, always normalized to [-PI, PI). The full turn is taken as twice the rounded
+// PI rather than the rounded TAU so that half turns stay exact at every precision.
+
+// Reflect
+#[derive(Copy, Clone, Debug, Default, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Hash, PartialEq)]
+// Comparison
+#[derive(Eq, PartialOrd, Ord, PartialEq, Hash)]
+#[serde(from = "Number", into = "Number")]
+pub struct Angle(Number);
+
+impl Angle {
+    pub const ZERO: Angle = Angle(Number::ZERO);
+
+    #[track_caller]
+    pub fn from_radians(radians: Number) -> Self {
+        let full_turn = Number::PI + Number::PI;
+        let radians = Number(radians.0.rem_euclid(full_turn.0));
+
+        if radians >= Number::PI {
+            Self(radians - full_turn)
+        } else {
+            Self(radians)
+        }
+    }
+
+    #[track_caller]
+    pub fn from_degrees(degrees: Number) -> Self {
+        let degrees = Number(degrees.0.rem_euclid(Number::from(360).0));
+        Self::from_radians(degrees * Number::PI / Number::from(180))
+    }
+
+    pub fn from_direction(direction: Vector2) -> Self {
+        direction.angle()
+    }
+
+    pub fn radians(self) -> Number {
+        self.0
+    }
+
+    pub fn degrees(self) -> Number {
+        self.0 * Number::from(180) / Number::PI
+    }
+
+    pub fn direction(self) -> Vector2 {
+        Vector2::from_angle(self)
+    }
+
+    pub fn sin_cos(self) -> (Number, Number) {
+        self.0.sin_cos()
+    }
+
+    pub fn sin(self) -> Number {
+        self.0.sin()
+    }
+
+    pub fn cos(self) -> Number {
+        self.0.cos()
+    }
+
+    // Returns the signed shortest arc going from self to target
+    pub fn difference(self, target: Angle) -> Angle {
+        target - self
+    }
+
+    // Turns toward target along the shortest arc by at most max_step radians
+    pub fn turn_toward(self, target: Angle, max_step: Number) -> Angle {
+        let difference = self.difference(target).radians();
+
+        if difference.abs() <= max_step {
+            target
+        } else {
+            Angle::from_radians(self.0 + max_step * difference.signum())
+        }
+    }
+
+    // Interpolates along the shortest arc
+    pub fn lerp(self, target: Angle, t: Number) -> Angle {
+        Angle::from_radians(self.0 + self.difference(target).radians() * t)
+    }
+}
+
+impl Add for Angle {
+    type Output = Angle;
+
+    #[track_caller]
+    fn add(self, rhs: Angle) -> Angle {
+        Angle::from_radians(self.0 + rhs.0)
+    }
+}
+impl AddAssign for Angle {
+    #[track_caller]
+    fn add_assign(&mut self, rhs: Angle) {
+        *self = *self + rhs;
+    }
+}
+impl Sub for Angle {
+    type Output = Angle;
+
+    #[track_caller]
+    fn sub(self, rhs: Angle) -> Angle {
+        Angle::from_radians(self.0 - rhs.0)
+    }
+}
+impl SubAssign for Angle {
+    #[track_caller]
+    fn sub_assign(&mut self, rhs: Angle) {
+        *self = *self - rhs;
+    }
+}
+impl Mul<Number> for Angle {
+    type Output = Angle;
+
+    #[track_caller]
+    fn mul(self, rhs: Number) -> Angle {
+        Angle::from_radians(self.0 * rhs)
+    }
+}
+impl Neg for Angle {
+    type Output = Angle;
+
+    #[track_caller]
+    fn neg(self) -> Angle {
+        Angle::from_radians(-self.0)
+    }
+}
+
+impl fmt::Display for Angle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rad", self.0)
+    }
+}
+
+impl From<Number> for Angle {
+    fn from(radians: Number) -> Self {
+        Angle::from_radians(radians)
+    }
+}
+impl From<Angle> for Number {
+    fn from(angle: Angle) -> Self {
+        angle.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::{Angle, Number, Vector2};
+
+    fn assert_near(a: Number, b: Number) {
+        assert!((a - b).abs() <= Number::DELTA * Number::from(2));
+    }
+
+    fn assert_near_degrees(a: Number, b: Number) {
+        assert!((a - b).abs() <= Number::from(3));
+    }
+
+    #[test]
+    fn normalization() {
+        assert!(Angle::from_radians(Number::PI * Number::from(2)) == Angle::ZERO);
+        assert!(Angle::from_radians(Number::PI * Number::from(-4)) == Angle::ZERO);
+        assert!(Angle::from_radians(Number::PI).radians() < Number::ZERO);
+        assert_near(
+            Angle::from_degrees(Number::from(450)).radians(),
+            Number::FRAC_PI_2,
+        );
+        assert_near_degrees(
+            Angle::from_degrees(Number::from(-90)).degrees(),
+            Number::from(-90),
+        );
+    }
+
+    #[test]
+    fn arcs() {
+        let a = Angle::from_degrees(Number::from(170));
+        let b = Angle::from_degrees(Number::from(-170));
+
+        assert_near_degrees(a.difference(b).degrees(), Number::from(20));
+        assert_near_degrees(b.difference(a).degrees(), Number::from(-20));
+        assert!(a.turn_toward(b, Number::ONE) == b);
+        assert!(
+            Angle::ZERO.turn_toward(Angle::from_radians(Number::FRAC_PI_2), Number::ONE)
+                == Angle::from_radians(Number::ONE)
+        );
+    }
+
+    #[test]
+    fn directions() {
+        assert_near(
+            Angle::from_direction(Vector2::UP).radians(),
+            Number::FRAC_PI_2,
+        );
+        let direction = Angle::from_radians(Number::PI).direction();
+        assert_near(direction.x, -Number::ONE);
+        assert_near(direction.y, Number::ZERO);
+    }
+}
@@ -0,0 +1,321 @@
+use bevy::math::{Vec2, Vec3};
+use rapier2d::prelude::{Isometry, Real, Vector};
+
+use crate::core::maths::number::FixedImpl;
+use crate::core::maths::overflow::overflow;
+use crate::core::maths::{Angle, Number, Vector2};
+use crate::core::transform::Transform2;
+
+// Every conversion between engine maths and float based types goes through Number::from_f32,
+// Number::from_f64, Number::to_f32 or Number::to_f64, so that rounding is the same on every peer.
+
+pub trait CheckedFrom<T>: Sized {
+    // Returns None when the conversion would lose precision or overflow.
+    fn checked_from(value: T) -> Option<Self>;
+}
+
+// Out of range values overflow as configured by the overflow policy. NaN has no sign to saturate
+// toward and infinities cannot wrap, both saturate or wrap to zero.
+macro_rules! float_to_number {
+    ($operation:literal, $value:expr) => {{
+        let value = $value;
+        Number(match FixedImpl::checked_from_num(value) {
+            Some(number) => number,
+            None => overflow(
+                $operation,
+                || {
+                    if value.is_nan() {
+                        FixedImpl::ZERO
+                    } else {
+                        FixedImpl::saturating_from_num(value)
+                    }
+                },
+                || {
+                    if value.is_finite() {
+                        FixedImpl::wrapping_from_num(value)
+                    } else {
+                        FixedImpl::ZERO
+                    }
+                },
+            ),
+        })
+    }};
+}
+
+impl Number {
+    // Rounds to the nearest representable number, ties to even.
+    #[track_caller]
+    pub fn from_f32(value: f32) -> Number {
+        float_to_number!("from_f32", value)
+    }
+
+    #[track_caller]
+    pub fn from_f64(value: f64) -> Number {
+        float_to_number!("from_f64", value)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        self.0.to_num()
+    }
+
+    pub fn to_f64(self) -> f64 {
+        self.0.to_num()
+    }
+}
+
+impl From<f32> for Number {
+    #[track_caller]
+    fn from(value: f32) -> Self {
+        Number::from_f32(value)
+    }
+}
+impl From<f64> for Number {
+    #[track_caller]
+    fn from(value: f64) -> Self {
+        Number::from_f64(value)
+    }
+}
+impl From<Number> for f32 {
+    fn from(number: Number) -> Self {
+        number.to_f32()
+    }
+}
+impl From<Number> for f64 {
+    fn from(number: Number) -> Self {
+        number.to_f64()
+    }
+}
+
+// Integers are exact, out of range values overflow as configured by the overflow policy.
+macro_rules! impl_integer_conversions {
+    ($($integer:ty),*) => {
+        $(
+            impl From<$integer> for Number {
+                #[track_caller]
+                fn from(value: $integer) -> Self {
+                    Number(match FixedImpl::checked_from_num(value) {
+                        Some(number) => number,
+                        None => overflow(
+                            "from",
+                            || FixedImpl::saturating_from_num(value),
+                            || FixedImpl::wrapping_from_num(value),
+                        ),
+                    })
+                }
+            }
+        )*
+    };
+}
+
+impl_integer_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+// Rounds toward negative infinity, out of range values overflow as configured by the overflow
+// policy.
+macro_rules! impl_number_to_integer {
+    ($($integer:ty),*) => {
+        $(
+            impl From<Number> for $integer {
+                #[track_caller]
+                fn from(number: Number) -> Self {
+                    match number.0.checked_to_num() {
+                        Some(integer) => integer,
+                        None => overflow(
+                            "into",
+                            || number.0.saturating_to_num(),
+                            || number.0.wrapping_to_num(),
+                        ),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_number_to_integer!(i32, u32, usize);
+
+impl CheckedFrom<f32> for Number {
+    fn checked_from(value: f32) -> Option<Number> {
+        FixedImpl::checked_from_num(value)
+            .filter(|number| number.to_num::<f32>() == value)
+            .map(Number)
+    }
+}
+impl CheckedFrom<f64> for Number {
+    fn checked_from(value: f64) -> Option<Number> {
+        FixedImpl::checked_from_num(value)
+            .filter(|number| number.to_num::<f64>() == value)
+            .map(Number)
+    }
+}
+
+impl From<Vector2> for Vec2 {
+    fn from(vector: Vector2) -> Self {
+        Vec2::new(vector.x.to_f32(), vector.y.to_f32())
+    }
+}
+impl From<Vec2> for Vector2 {
+    #[track_caller]
+    fn from(vector: Vec2) -> Self {
+        Vector2 {
+            x: Number::from_f32(vector.x),
+            y: Number::from_f32(vector.y),
+        }
+    }
+}
+impl CheckedFrom<Vec2> for Vector2 {
+    fn checked_from(vector: Vec2) -> Option<Self> {
+        Some(Vector2 {
+            x: Number::checked_from(vector.x)?,
+            y: Number::checked_from(vector.y)?,
+        })
+    }
+}
+
+// The z component is zero when converting to Vec3 and dropped when converting from Vec3.
+impl From<Vector2> for Vec3 {
+    fn from(vector: Vector2) -> Self {
+        Vec2::from(vector).extend(0.0)
+    }
+}
+impl From<Vec3> for Vector2 {
+    #[track_caller]
+    fn from(vector: Vec3) -> Self {
+        vector.truncate().into()
+    }
+}
+impl CheckedFrom<Vec3> for Vector2 {
+    fn checked_from(vector: Vec3) -> Option<Self> {
+        Vector2::checked_from(vector.truncate())
+    }
+}
+
+impl From<Vector2> for Vector<Real> {
+    fn from(vector: Vector2) -> Self {
+        Vector::new(vector.x.to_f32(), vector.y.to_f32())
+    }
+}
+impl From<Vector<Real>> for Vector2 {
+    #[track_caller]
+    fn from(vector: Vector<Real>) -> Self {
+        Vector2 {
+            x: Number::from_f32(vector.x),
+            y: Number::from_f32(vector.y),
+        }
+    }
+}
+impl CheckedFrom<Vector<Real>> for Vector2 {
+    fn checked_from(vector: Vector<Real>) -> Option<Self> {
+        Some(Vector2 {
+            x: Number::checked_from(vector.x)?,
+            y: Number::checked_from(vector.y)?,
+        })
+    }
+}
+
+// Isometries converted to vectors must not be rotated, convert them to Transform2 otherwise.
+impl From<Vector2> for Isometry<Real> {
+    fn from(vector: Vector2) -> Self {
+        Isometry::translation(vector.x.to_f32(), vector.y.to_f32())
+    }
+}
+impl CheckedFrom<Isometry<Real>> for Vector2 {
+    fn checked_from(isometry: Isometry<Real>) -> Option<Self> {
+        if isometry.rotation.angle() != 0.0 {
+            return None;
+        }
+
+        Vector2::checked_from(isometry.translation.vector)
+    }
+}
+
+impl From<Isometry<Real>> for Transform2 {
+    #[track_caller]
+    fn from(isometry: Isometry<Real>) -> Self {
+        Transform2 {
+            pos: isometry.translation.vector.into(),
+            rotation: Angle::from_radians(Number::from_f32(isometry.rotation.angle())),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Vec2, Vec3};
+    use rapier2d::prelude::{Isometry, Real, Vector};
+
+    use crate::core::maths::{
+        Angle, CheckedFrom, Number, OverflowPolicy, Vector2, OVERFLOW_POLICY,
+    };
+    use crate::core::transform::Transform2;
+
+    #[test]
+    fn round_trips() {
+        let vector = Vector2::new(12.5, -3.25);
+
+        assert!(Vector2::from(Vec2::from(vector)) == vector);
+        assert!(Vector2::from(Vec3::from(vector)) == vector);
+        assert!(Vector2::from(Vector::<Real>::from(vector)) == vector);
+        assert!(Vector2::checked_from(Isometry::<Real>::from(vector)) == Some(vector));
+        assert!(f32::from(Number::from(-2.75f32)) == -2.75);
+        assert!(f64::from(Number::from(0.5)) == 0.5);
+        assert!(i32::from(Number::from(-3.5)) == -4);
+        assert!(Vec3::from(vector).z == 0.0);
+    }
+
+    #[test]
+    fn checked() {
+        assert!(Number::checked_from(1.5f32) == Some(Number::from(1.5)));
+        assert!(Number::checked_from(f32::MAX).is_none());
+        assert!(Number::checked_from(1.0e-9f32).is_none());
+        assert!(Number::checked_from(f32::NAN).is_none());
+        assert!(Number::checked_from(-0.25f64) == Some(Number::from(-0.25)));
+        assert!(Vector2::checked_from(Vec2::new(1.0, 2.0)) == Some(Vector2::new(1, 2)));
+        assert!(Vector2::checked_from(Vector::new(1.0e-9, 2.0)).is_none());
+        assert!(Vector2::checked_from(Vec3::new(1.0, 2.0, 1.0e-9)) == Some(Vector2::new(1, 2)));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let convert = |value: f64| std::panic::catch_unwind(|| Number::from_f64(value)).ok();
+        let beyond = Number::MAX.to_f64() * 4.0;
+        let results = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, beyond, -beyond].map(convert);
+        let negative = std::panic::catch_unwind(|| u32::from(-Number::ONE)).ok();
+
+        match OVERFLOW_POLICY {
+            OverflowPolicy::Checked => {
+                assert!(results.iter().all(Option::is_none));
+                assert!(negative.is_none());
+            }
+            OverflowPolicy::Saturating => {
+                assert!(
+                    results
+                        == [
+                            Some(Number::ZERO),
+                            Some(Number::MAX),
+                            Some(Number::MIN),
+                            Some(Number::MAX),
+                            Some(Number::MIN)
+                        ]
+                );
+                assert!(negative == Some(0));
+            }
+            OverflowPolicy::Wrapping => {
+                assert!(results[..3] == [Some(Number::ZERO); 3]);
+                assert!(results[3..].iter().all(Option::is_some));
+                assert!(negative == Some(u32::MAX));
+            }
+        }
+    }
+
+    #[test]
+    fn isometries() {
+        let rotated = Isometry::new(Vector::new(1.0, 2.0), std::f32::consts::FRAC_PI_2);
+        let transform = Transform2::from(rotated);
+
+        assert!(Vector2::checked_from(rotated).is_none());
+        assert!(transform.pos == Vector2::new(1, 2));
+        assert!(transform.scale == Vector2::ONE);
+        assert!(transform.rotation == Angle::from_radians(Number::FRAC_PI_2));
+    }
+}
@@ -0,0 +1,450 @@
+use bevy::reflect::{FromReflect, Reflect};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::core::maths::{Number, Vector2};
+use crate::core::transform::Transform2;
+
+// Ray
+
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub struct Ray {
+    pub origin: Vector2,
+    pub direction: Vector2,
+}
+
+impl Ray {
+    pub fn new(origin: Vector2, direction: Vector2) -> Self {
+        Self { origin, direction }
+    }
+
+    pub fn point_at(&self, toi: Number) -> Vector2 {
+        self.origin + self.direction * toi
+    }
+
+    pub fn transformed(&self, transform: &Transform2) -> Ray {
+        Ray {
+            origin: transform.transform_point(self.origin),
+            direction: transform.transform_vector(self.direction),
+        }
+    }
+}
+
+// Aabb
+
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub struct Aabb {
+    pub min: Vector2,
+    pub max: Vector2,
+}
+
+impl Aabb {
+    pub fn new(min: Vector2, max: Vector2) -> Self {
+        Self {
+            min: min.min(max),
+            max: min.max(max),
+        }
+    }
+
+    pub fn from_center_half_extents(center: Vector2, half_extents: Vector2) -> Self {
+        Self::new(center - half_extents, center + half_extents)
+    }
+
+    pub fn from_points(points: &[Vector2]) -> Self {
+        let first = points.first().copied().unwrap_or_default();
+
+        points
+            .iter()
+            .fold(Self::new(first, first), |aabb, point| Aabb {
+                min: aabb.min.min(*point),
+                max: aabb.max.max(*point),
+            })
+    }
+
+    pub fn center(&self) -> Vector2 {
+        (self.min + self.max) / Number::from(2)
+    }
+
+    pub fn half_extents(&self) -> Vector2 {
+        (self.max - self.min) / Number::from(2)
+    }
+
+    pub fn size(&self) -> Vector2 {
+        self.max - self.min
+    }
+
+    pub fn corners(&self) -> [Vector2; 4] {
+        [
+            self.min,
+            Vector2 {
+                x: self.max.x,
+                y: self.min.y,
+            },
+            self.max,
+            Vector2 {
+                x: self.min.x,
+                y: self.max.y,
+            },
+        ]
+    }
+
+    pub fn merged(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn contains_point(&self, point: Vector2) -> bool {
+        point.x >= self.min.x
+            && point.x <= self.max.x
+            && point.y >= self.min.y
+            && point.y <= self.max.y
+    }
+
+    pub fn contains(&self, other: &Aabb) -> bool {
+        self.contains_point(other.min) && self.contains_point(other.max)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+    }
+
+    pub fn intersects_circle(&self, circle: &Circle) -> bool {
+        circle.intersects_aabb(self)
+    }
+
+    pub fn closest_point(&self, point: Vector2) -> Vector2 {
+        point.max(self.min).min(self.max)
+    }
+
+    // Returns the time of impact along the ray, up to max_toi.
+    pub fn cast_ray(&self, ray: &Ray, max_toi: Number) -> Option<Number> {
+        let mut toi_min = Number::ZERO;
+        let mut toi_max = max_toi;
+
+        for (origin, direction, min, max) in [
+            (ray.origin.x, ray.direction.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.direction.y, self.min.y, self.max.y),
+        ] {
+            if direction == Number::ZERO {
+                if origin < min || origin > max {
+                    return None;
+                }
+            } else {
+                let toi_1 = (min - origin) / direction;
+                let toi_2 = (max - origin) / direction;
+
+                toi_min = toi_min.max(toi_1.min(toi_2));
+                toi_max = toi_max.min(toi_1.max(toi_2));
+                if toi_min > toi_max {
+                    return None;
+                }
+            }
+        }
+
+        Some(toi_min)
+    }
+
+    // Returns the fraction of motion at which this box, moving by motion, touches other.
+    pub fn sweep(&self, motion: Vector2, other: &Aabb) -> Option<Number> {
+        let expanded = Aabb::from_center_half_extents(
+            other.center(),
+            other.half_extents() + self.half_extents(),
+        );
+
+        expanded.cast_ray(&Ray::new(self.center(), motion), Number::ONE)
+    }
+
+    pub fn transformed(&self, transform: &Transform2) -> Aabb {
+        Aabb::from_points(
+            &self
+                .corners()
+                .map(|corner| transform.transform_point(corner)),
+        )
+    }
+}
+
+// Circle
+
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub struct Circle {
+    pub center: Vector2,
+    pub radius: Number,
+}
+
+impl Circle {
+    pub fn new(center: Vector2, radius: Number) -> Self {
+        Self { center, radius }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_center_half_extents(self.center, Vector2::splat(self.radius))
+    }
+
+    pub fn contains_point(&self, point: Vector2) -> bool {
+        (point - self.center).cmp_length(self.radius) != Ordering::Greater
+    }
+
+    pub fn contains(&self, other: &Circle) -> bool {
+        other.radius <= self.radius
+            && self.center.distance(other.center) <= self.radius - other.radius
+    }
+
+    pub fn intersects(&self, other: &Circle) -> bool {
+        (other.center - self.center).cmp_length(self.radius + other.radius) != Ordering::Greater
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.contains_point(aabb.closest_point(self.center))
+    }
+
+    pub fn closest_point(&self, point: Vector2) -> Vector2 {
+        if self.contains_point(point) {
+            point
+        } else {
+            self.center + (point - self.center).normalize() * self.radius
+        }
+    }
+
+    // Returns the time of impact along the ray, up to max_toi.
+    pub fn cast_ray(&self, ray: &Ray, max_toi: Number) -> Option<Number> {
+        let offset = ray.origin - self.center;
+        if offset.cmp_length(self.radius) != Ordering::Greater {
+            return Some(Number::ZERO);
+        }
+
+        let length = ray.direction.length();
+        if length == Number::ZERO {
+            return None;
+        }
+
+        // Works on distances rather than on the squared terms of the quadratic, which overflow
+        // for large circles at low integer precisions
+        let direction = ray.direction / length;
+        let along = -offset.dot(direction);
+        if along < Number::ZERO {
+            return None;
+        }
+
+        let distance = (offset + direction * along).length();
+        if distance > self.radius {
+            return None;
+        }
+
+        let half_chord = (self.radius - distance).sqrt_mul(self.radius + distance);
+        let toi = (along - half_chord) / length;
+        (toi <= max_toi).then(|| toi.max(Number::ZERO))
+    }
+
+    // Returns the fraction of motion at which this circle, moving by motion, touches other.
+    pub fn sweep(&self, motion: Vector2, other: &Circle) -> Option<Number> {
+        Circle::new(other.center, other.radius + self.radius)
+            .cast_ray(&Ray::new(self.center, motion), Number::ONE)
+    }
+
+    pub fn transformed(&self, transform: &Transform2) -> Circle {
+        Circle {
+            center: transform.transform_point(self.center),
+            radius: self.radius * transform.scale.x.abs().max(transform.scale.y.abs()),
+        }
+    }
+}
+
+// Segment
+
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Reflect, FromReflect, Serialize, Deserialize,
+)]
+pub struct Segment {
+    pub a: Vector2,
+    pub b: Vector2,
+}
+
+impl Segment {
+    pub fn new(a: Vector2, b: Vector2) -> Self {
+        Self { a, b }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(self.a, self.b)
+    }
+
+    pub fn length(&self) -> Number {
+        self.a.distance(self.b)
+    }
+
+    pub fn closest_point(&self, point: Vector2) -> Vector2 {
+        let length = self.length();
+        if length == Number::ZERO {
+            return self.a;
+        }
+
+        let direction = (self.b - self.a) / length;
+        let along = (point - self.a).dot(direction).clamp(Number::ZERO, length);
+        self.a + direction * along
+    }
+
+    pub fn contains_point(&self, point: Vector2) -> bool {
+        self.closest_point(point) == point
+    }
+
+    // Returns the intersection point of both segments, if any.
+    pub fn intersection(&self, other: &Segment) -> Option<Vector2> {
+        let direction = self.b - self.a;
+
+        other
+            .cast_ray(&Ray::new(self.a, direction), Number::ONE)
+            .map(|toi| self.a + direction * toi)
+    }
+
+    pub fn intersects(&self, other: &Segment) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    pub fn intersects_circle(&self, circle: &Circle) -> bool {
+        circle.contains_point(self.closest_point(circle.center))
+    }
+
+    // Returns the time of impact along the ray, up to max_toi. Parallel rays never hit.
+    pub fn cast_ray(&self, ray: &Ray, max_toi: Number) -> Option<Number> {
+        let direction = self.b - self.a;
+        let denominator = ray.direction.cross(direction);
+
+        if denominator == Number::ZERO {
+            return None;
+        }
+
+        let offset = self.a - ray.origin;
+        let toi = offset.cross(direction) / denominator;
+        let u = offset.cross(ray.direction) / denominator;
+
+        (toi >= Number::ZERO && toi <= max_toi && u >= Number::ZERO && u <= Number::ONE)
+            .then_some(toi)
+    }
+
+    pub fn transformed(&self, transform: &Transform2) -> Segment {
+        Segment {
+            a: transform.transform_point(self.a),
+            b: transform.transform_point(self.b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::{Aabb, Angle, Circle, Number, Ray, Segment, Vector2};
+    use crate::core::transform::Transform2;
+
+    #[test]
+    fn aabb() {
+        let aabb = Aabb::new(Vector2::new(0, 0), Vector2::new(4, 2));
+
+        assert!(aabb.contains_point(Vector2::new(2, 1)));
+        assert!(!aabb.contains_point(Vector2::new(5, 1)));
+        assert!(aabb.contains(&Aabb::new(Vector2::new(1, 1), Vector2::new(2, 2))));
+        assert!(aabb.intersects(&Aabb::new(Vector2::new(3, 1), Vector2::new(6, 6))));
+        assert!(!aabb.intersects(&Aabb::new(Vector2::new(5, 1), Vector2::new(6, 6))));
+        assert!(aabb.closest_point(Vector2::new(6, -3)) == Vector2::new(4, 0));
+        assert!(aabb.center() == Vector2::new(2, 1));
+    }
+
+    #[test]
+    fn aabb_casts() {
+        let aabb = Aabb::new(Vector2::new(4, -1), Vector2::new(6, 1));
+        let ray = Ray::new(Vector2::ZERO, Vector2::RIGHT);
+
+        assert!(aabb.cast_ray(&ray, Number::from(10)) == Some(Number::from(4)));
+        assert!(aabb.cast_ray(&ray, Number::from(3)).is_none());
+        assert!(aabb
+            .cast_ray(&Ray::new(Vector2::ZERO, Vector2::UP), Number::from(10))
+            .is_none());
+
+        let mover = Aabb::from_center_half_extents(Vector2::ZERO, Vector2::ONE);
+        assert!(mover.sweep(Vector2::new(6, 0), &aabb) == Some(Number::from(0.5)));
+        assert!(mover.sweep(Vector2::new(0, 6), &aabb).is_none());
+    }
+
+    #[test]
+    fn circle() {
+        let circle = Circle::new(Vector2::ZERO, Number::from(2));
+
+        assert!(circle.contains_point(Vector2::new(1, 1)));
+        assert!(!circle.contains_point(Vector2::new(2, 2)));
+        assert!(circle.intersects(&Circle::new(Vector2::new(3, 0), Number::ONE)));
+        assert!(!circle.intersects(&Circle::new(Vector2::new(4, 0), Number::ONE)));
+        assert!(circle.intersects_aabb(&Aabb::new(Vector2::new(1, -1), Vector2::new(3, 1))));
+        assert!(circle.closest_point(Vector2::new(4, 0)) == Vector2::new(2, 0));
+        assert!(
+            circle.cast_ray(
+                &Ray::new(Vector2::new(-5, 0), Vector2::RIGHT),
+                Number::from(10)
+            ) == Some(Number::from(3))
+        );
+        assert!(
+            Circle::new(Vector2::new(-5, 0), Number::ONE).sweep(Vector2::new(4, 0), &circle)
+                == Some(Number::from(0.5))
+        );
+
+        // Squared distances overflow the smallest number format from about 181
+        let large = Circle::new(Vector2::new(1000, 0), Number::from(250));
+        assert!(large.contains_point(Vector2::new(1150, 200)));
+        assert!(!large.contains_point(Vector2::new(1200, 200)));
+        assert!(large.intersects(&Circle::new(Vector2::new(1000, 400), Number::from(150))));
+        assert!(
+            large.cast_ray(&Ray::new(Vector2::ZERO, Vector2::RIGHT), Number::from(1000))
+                == Some(Number::from(750))
+        );
+        assert!(large
+            .cast_ray(&Ray::new(Vector2::ZERO, Vector2::UP), Number::from(1000))
+            .is_none());
+    }
+
+    #[test]
+    fn segment() {
+        let segment = Segment::new(Vector2::new(0, 0), Vector2::new(4, 0));
+        let crossing = Segment::new(Vector2::new(2, -2), Vector2::new(2, 2));
+
+        assert!(segment.closest_point(Vector2::new(1, 3)) == Vector2::new(1, 0));
+        assert!(segment.closest_point(Vector2::new(-3, 3)) == Vector2::new(0, 0));
+        assert!(segment.intersection(&crossing) == Some(Vector2::new(2, 0)));
+        assert!(!segment.intersects(&Segment::new(Vector2::new(0, 1), Vector2::new(4, 1))));
+        assert!(segment.intersects_circle(&Circle::new(Vector2::new(2, 1), Number::ONE)));
+
+        let long = Segment::new(Vector2::new(-1000, 0), Vector2::new(1000, 0));
+        assert!(long.closest_point(Vector2::new(300, 500)) == Vector2::new(300, 0));
+        assert!(long.closest_point(Vector2::new(2000, 500)) == Vector2::new(1000, 0));
+    }
+
+    #[test]
+    fn transformed() {
+        let transform = Transform2 {
+            pos: Vector2::new(10, 0),
+            scale: Vector2::new(2, 2),
+            rotation: Angle::from_radians(Number::FRAC_PI_2),
+            ..Default::default()
+        };
+
+        assert!(
+            Segment::new(Vector2::ZERO, Vector2::RIGHT).transformed(&transform)
+                == Segment::new(Vector2::new(10, 0), Vector2::new(10, 2))
+        );
+        assert!(
+            Circle::new(Vector2::ZERO, Number::ONE).transformed(&transform)
+                == Circle::new(Vector2::new(10, 0), Number::from(2))
+        );
+        assert!(
+            Aabb::new(Vector2::ZERO, Vector2::ONE).transformed(&transform)
+                == Aabb::new(Vector2::new(8, 0), Vector2::new(10, 2))
+        );
+    }
+}
@@ -1,5 +1,18 @@
+mod affine2;
+mod angle;
+mod conversions;
+mod geometry;
 mod number;
+mod number_math;
+mod overflow;
+mod random;
 mod vector2;
 
+pub use affine2::Affine2;
+pub use angle::Angle;
+pub use conversions::CheckedFrom;
+pub use geometry::{Aabb, Circle, Ray, Segment};
 pub use number::Number;
+pub use overflow::{OverflowContext, OverflowPolicy, OVERFLOW_POLICY};
+pub use random::RollbackRng;
 pub use vector2::Vector2;
@@ -1,46 +1,137 @@
-use bevy::reflect::impl_reflect_value;
 use bevy::reflect::ReflectDeserialize;
-use derive_more::*;
-use fixed::traits::ToFixed;
-use fixed::types::extra::U4;
-use fixed::FixedI32;
+use bevy::reflect::{impl_from_reflect_value, impl_reflect_value};
+use fixed::traits::Fixed;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-type FixedImpl = FixedI32<U4>;
+use crate::core::maths::overflow::{division_by_zero, overflow};
+
+#[cfg(all(feature = "number-i16f16", feature = "number-i48f16"))]
+compile_error!("features \"number-i16f16\" and \"number-i48f16\" are mutually exclusive");
+
+#[cfg(not(any(feature = "number-i16f16", feature = "number-i48f16")))]
+pub(crate) type FixedImpl = fixed::types::I28F4;
+#[cfg(feature = "number-i16f16")]
+pub(crate) type FixedImpl = fixed::types::I16F16;
+#[cfg(feature = "number-i48f16")]
+pub(crate) type FixedImpl = fixed::types::I48F16;
+pub(crate) type FixedBits = <FixedImpl as Fixed>::Bits;
 
 // Reflect
 #[derive(Copy, Clone, Default, Serialize, Deserialize)]
 // Comparison
-#[derive(Eq, PartialOrd, Ord, PartialEq)]
-// Math operators
-#[derive(Add, AddAssign, Mul, MulAssign, Sub, SubAssign, Div, DivAssign, Neg)]
-#[mul(forward)]
+#[derive(Eq, PartialOrd, Ord, PartialEq, Hash)]
+#[serde(transparent)]
 pub struct Number(pub FixedImpl);
 
-impl_reflect_value!(Number(Serialize, Deserialize));
+// Math operators, overflowing as configured by the overflow policy
+macro_rules! impl_number_operator {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $checked:ident, $saturating:ident, $wrapping:ident) => {
+        impl $trait for Number {
+            type Output = Number;
+
+            #[track_caller]
+            fn $method(self, rhs: Number) -> Number {
+                // A closure would report its own location instead of the caller's
+                Number(match self.0.$checked(rhs.0) {
+                    Some(result) => result,
+                    None => overflow(
+                        stringify!($method),
+                        || self.0.$saturating(rhs.0),
+                        || self.0.$wrapping(rhs.0),
+                    ),
+                })
+            }
+        }
+        impl $assign_trait for Number {
+            #[track_caller]
+            fn $assign_method(&mut self, rhs: Number) {
+                *self = $trait::$method(*self, rhs);
+            }
+        }
+    };
+}
+
+impl_number_operator!(
+    Add,
+    add,
+    AddAssign,
+    add_assign,
+    checked_add,
+    saturating_add,
+    wrapping_add
+);
+impl_number_operator!(
+    Sub,
+    sub,
+    SubAssign,
+    sub_assign,
+    checked_sub,
+    saturating_sub,
+    wrapping_sub
+);
+impl_number_operator!(
+    Mul,
+    mul,
+    MulAssign,
+    mul_assign,
+    checked_mul,
+    saturating_mul,
+    wrapping_mul
+);
+
+// Division by zero is not an overflow, saturating_div and wrapping_div would panic on it
+impl Div for Number {
+    type Output = Number;
 
-impl From<Number> for f32 {
-    fn from(val: Number) -> Self {
-        FixedImpl::to_num(val.0)
+    #[track_caller]
+    fn div(self, rhs: Number) -> Number {
+        if rhs == Number::ZERO {
+            return division_by_zero(self);
+        }
+
+        Number(match self.0.checked_div(rhs.0) {
+            Some(result) => result,
+            None => overflow(
+                "div",
+                || self.0.saturating_div(rhs.0),
+                || self.0.wrapping_div(rhs.0),
+            ),
+        })
     }
 }
-impl From<Number> for i32 {
-    fn from(val: Number) -> Self {
-        FixedImpl::to_num(val.0)
+impl DivAssign for Number {
+    #[track_caller]
+    fn div_assign(&mut self, rhs: Number) {
+        *self = *self / rhs;
     }
 }
-impl From<Number> for u32 {
-    fn from(val: Number) -> Self {
-        FixedImpl::to_num(val.0)
+
+impl Neg for Number {
+    type Output = Number;
+
+    #[track_caller]
+    fn neg(self) -> Number {
+        Number(match self.0.checked_neg() {
+            Some(result) => result,
+            None => overflow("neg", || self.0.saturating_neg(), || self.0.wrapping_neg()),
+        })
     }
 }
-impl From<Number> for usize {
-    fn from(val: Number) -> Self {
-        FixedImpl::to_num(val.0)
+
+impl_reflect_value!(Number(Hash, PartialEq, Serialize, Deserialize));
+impl_from_reflect_value!(Number);
+
+// Formatting, exact decimal representation of the fixed-point value
+
+impl fmt::Debug for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
     }
 }
-impl<Src: ToFixed> From<Src> for Number {
-    fn from(src: Src) -> Self {
-        Self(FixedImpl::from_num(src))
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
     }
 }
@@ -0,0 +1,368 @@
+use crate::core::maths::number::{FixedBits, FixedImpl};
+use crate::core::maths::Number;
+
+// Internal computations are done on i64 with Q fractional bits so that results are bit-identical
+// on every platform, whatever the precision of Number.
+const Q: u32 = 30;
+const F: u32 = FixedImpl::FRAC_NBITS;
+
+const PI_Q: i64 = 3_373_259_426;
+const FRAC_PI_2_Q: i64 = 1_686_629_713;
+const TAU_Q: i64 = 6_746_518_852;
+const CORDIC_GAIN_Q: i64 = 652_032_874;
+const CORDIC_ATAN_Q: [i64; 31] = [
+    843_314_857,
+    497_837_829,
+    263_043_837,
+    133_525_159,
+    67_021_687,
+    33_543_516,
+    16_775_851,
+    8_388_437,
+    4_194_283,
+    2_097_149,
+    1_048_576,
+    524_288,
+    262_144,
+    131_072,
+    65_536,
+    32_768,
+    16_384,
+    8_192,
+    4_096,
+    2_048,
+    1_024,
+    512,
+    256,
+    128,
+    64,
+    32,
+    16,
+    8,
+    4,
+    2,
+    1,
+];
+
+const fn from_q_const(q: i64) -> Number {
+    Number(FixedImpl::from_bits(
+        ((q + (1 << (Q - F - 1))) >> (Q - F)) as FixedBits,
+    ))
+}
+
+fn to_q(value: Number) -> i128 {
+    i128::from(value.0.to_bits()) << (Q - F)
+}
+
+fn from_q(q: i64) -> Number {
+    let bits = (q + (1 << (Q - F - 1))) >> (Q - F);
+    let bits = FixedBits::try_from(bits).unwrap_or(if bits < 0 {
+        FixedBits::MIN
+    } else {
+        FixedBits::MAX
+    });
+
+    Number(FixedImpl::from_bits(bits))
+}
+
+fn isqrt(value: u128) -> u128 {
+    if value == 0 {
+        return 0;
+    }
+
+    let mut result = 0;
+    let mut remainder = value;
+    let mut bit = 1 << ((127 - value.leading_zeros()) & !1);
+
+    while bit != 0 {
+        if remainder >= result + bit {
+            remainder -= result + bit;
+            result = (result >> 1) + bit;
+        } else {
+            result >>= 1;
+        }
+        bit >>= 2;
+    }
+
+    result
+}
+
+// Returns (cos, sin) of an angle in [-PI/2, PI/2], both in Q.
+fn cordic_rotate(angle: i64) -> (i64, i64) {
+    let (mut x, mut y, mut z) = (CORDIC_GAIN_Q, 0, angle);
+
+    for (i, atan) in CORDIC_ATAN_Q.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if z >= 0 {
+            x -= dx;
+            y += dy;
+            z -= atan;
+        } else {
+            x += dx;
+            y -= dy;
+            z += atan;
+        }
+    }
+
+    (x, y)
+}
+
+// Returns the angle of a vector with x >= 0, in Q.
+fn cordic_vector(mut x: i64, mut y: i64) -> i64 {
+    let mut z = 0;
+
+    for (i, atan) in CORDIC_ATAN_Q.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if y > 0 {
+            x += dx;
+            y -= dy;
+            z += atan;
+        } else {
+            x -= dx;
+            y += dy;
+            z -= atan;
+        }
+    }
+
+    z
+}
+
+impl Number {
+    pub const ZERO: Number = Number(FixedImpl::from_bits(0));
+    pub const ONE: Number = Number(FixedImpl::from_bits(1 << F));
+    pub const DELTA: Number = Number(FixedImpl::from_bits(1));
+    pub const MIN: Number = Number(FixedImpl::MIN);
+    pub const MAX: Number = Number(FixedImpl::MAX);
+    pub const PI: Number = from_q_const(PI_Q);
+    pub const FRAC_PI_2: Number = from_q_const(FRAC_PI_2_Q);
+    pub const TAU: Number = from_q_const(TAU_Q);
+
+    pub fn abs(self) -> Number {
+        Number(self.0.abs())
+    }
+
+    pub fn signum(self) -> Number {
+        Number(self.0.signum())
+    }
+
+    pub fn floor(self) -> Number {
+        Number(self.0.floor())
+    }
+
+    pub fn ceil(self) -> Number {
+        Number(self.0.ceil())
+    }
+
+    pub fn round(self) -> Number {
+        Number(self.0.round())
+    }
+
+    pub fn fract(self) -> Number {
+        Number(self.0.frac())
+    }
+
+    pub fn lerp(self, other: Number, t: Number) -> Number {
+        self + (other - self) * t
+    }
+
+    // Interpolates between integers on 128 bits so that no range of i32 overflows or loses
+    // precision, rounding half away from zero like round. Saturates when t extrapolates past i32.
+    pub fn lerp_i32(self, from: i32, to: i32) -> i32 {
+        let product = (i128::from(to) - i128::from(from)) * i128::from(self.0.to_bits());
+        let half = 1 << (F - 1);
+        let offset = if product < 0 {
+            -((-product + half) >> F)
+        } else {
+            (product + half) >> F
+        };
+        let value = (i128::from(from) + offset).clamp(i128::from(i32::MIN), i128::from(i32::MAX));
+
+        value as i32
+    }
+
+    // Rounds toward zero, negative numbers yield zero.
+    pub fn sqrt(self) -> Number {
+        let bits = i128::from(self.0.to_bits());
+        if bits <= 0 {
+            return Number::ZERO;
+        }
+
+        from_q(isqrt((bits as u128) << F) as i64 * (1 << (Q - F)))
+    }
+
+    // Length of (self, other), squared on 128 bits so that it only overflows when the result does,
+    // in which case it saturates. Rounds toward zero.
+    pub fn hypot(self, other: Number) -> Number {
+        let x = i128::from(self.0.to_bits()).unsigned_abs();
+        let y = i128::from(other.0.to_bits()).unsigned_abs();
+        let bits = FixedBits::try_from(isqrt(x * x + y * y)).unwrap_or(FixedBits::MAX);
+
+        Number(FixedImpl::from_bits(bits))
+    }
+
+    // Square root of self * other multiplied on 128 bits, negative operands yield zero like sqrt.
+    pub fn sqrt_mul(self, other: Number) -> Number {
+        if self <= Number::ZERO || other <= Number::ZERO {
+            return Number::ZERO;
+        }
+
+        let x = i128::from(self.0.to_bits()).unsigned_abs();
+        let y = i128::from(other.0.to_bits()).unsigned_abs();
+        let bits = FixedBits::try_from(isqrt(x * y)).unwrap_or(FixedBits::MAX);
+
+        Number(FixedImpl::from_bits(bits))
+    }
+
+    pub fn sin_cos(self) -> (Number, Number) {
+        let mut angle = to_q(self).rem_euclid(TAU_Q as i128) as i64;
+        let mut flip = false;
+
+        if angle > PI_Q {
+            angle -= TAU_Q;
+        }
+        if angle > FRAC_PI_2_Q {
+            angle = PI_Q - angle;
+            flip = true;
+        } else if angle < -FRAC_PI_2_Q {
+            angle = -PI_Q - angle;
+            flip = true;
+        }
+
+        let (cos, sin) = cordic_rotate(angle);
+        (from_q(sin), from_q(if flip { -cos } else { cos }))
+    }
+
+    pub fn sin(self) -> Number {
+        self.sin_cos().0
+    }
+
+    pub fn cos(self) -> Number {
+        self.sin_cos().1
+    }
+
+    pub fn atan2(self, x: Number) -> Number {
+        let (mut x, mut y) = (to_q(x), to_q(self));
+
+        if x == 0 && y == 0 {
+            return Number::ZERO;
+        }
+        // keep enough headroom for the CORDIC gain
+        while x.abs() >= 1 << 60 || y.abs() >= 1 << 60 {
+            x >>= 1;
+            y >>= 1;
+        }
+        let (x, y) = (x as i64, y as i64);
+
+        if x >= 0 {
+            from_q(cordic_vector(x, y))
+        } else {
+            let angle = cordic_vector(-x, -y) + PI_Q;
+            from_q(if angle > PI_Q { angle - TAU_Q } else { angle })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::Number;
+
+    fn assert_near(value: Number, reference: f64) {
+        let tolerance = f64::from(f32::from(Number::DELTA));
+        let difference = (f64::from(f32::from(value)) - reference).abs();
+
+        assert!(
+            difference <= tolerance,
+            "{} differs from reference {} by more than {}",
+            f32::from(value),
+            reference,
+            tolerance
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_near(Number::ZERO, 0.0);
+        assert_near(Number::ONE, 1.0);
+        assert_near(Number::PI, std::f64::consts::PI);
+        assert_near(Number::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+        assert_near(Number::TAU, std::f64::consts::TAU);
+    }
+
+    #[test]
+    fn rounding() {
+        assert!(Number::from(-2.5).abs() == Number::from(2.5));
+        assert!(Number::from(-2.5).signum() == Number::from(-1));
+        assert!(Number::from(0).signum() == Number::ZERO);
+        assert!(Number::from(2.5).floor() == Number::from(2));
+        assert!(Number::from(-2.5).floor() == Number::from(-3));
+        assert!(Number::from(2.5).ceil() == Number::from(3));
+        assert!(Number::from(-2.5).ceil() == Number::from(-2));
+        assert!(Number::from(-2.25).fract() == Number::from(0.75));
+        assert!(Number::from(3).clamp(Number::ZERO, Number::ONE) == Number::ONE);
+    }
+
+    #[test]
+    fn lerp() {
+        let (a, b) = (Number::from(2), Number::from(10));
+
+        assert!(a.lerp(b, Number::ZERO) == a);
+        assert!(a.lerp(b, Number::ONE) == b);
+        assert!(a.lerp(b, Number::from(0.5)) == Number::from(6));
+
+        assert!(Number::from(0.25).lerp_i32(0, 10) == 3);
+        assert!(Number::from(0.25).lerp_i32(0, -10) == -3);
+        assert!(Number::from(0.5).lerp_i32(i32::MIN, i32::MAX) == 0);
+        assert!(Number::ONE.lerp_i32(i32::MIN, i32::MAX) == i32::MAX);
+        assert!(Number::from(2).lerp_i32(0, i32::MAX) == i32::MAX);
+    }
+
+    #[test]
+    fn sqrt() {
+        for value in [0.0, 0.25, 1.0, 2.0, 3.0, 10.0, 144.0, 1000.0, 30000.0] {
+            assert_near(Number::from(value).sqrt(), f64::sqrt(value));
+        }
+        assert!(Number::from(-4).sqrt() == Number::ZERO);
+        assert!(Number::from(16).sqrt() == Number::from(4));
+    }
+
+    #[test]
+    fn hypot() {
+        assert!(Number::from(3).hypot(Number::from(-4)) == Number::from(5));
+        assert!(Number::from(-9000).hypot(Number::from(12000)) == Number::from(15000));
+        assert!(Number::ZERO.hypot(Number::ZERO) == Number::ZERO);
+        assert!(Number::MAX.hypot(Number::MAX) == Number::MAX);
+        assert!(Number::from(2).sqrt_mul(Number::from(8)) == Number::from(4));
+        assert!(Number::from(9000).sqrt_mul(Number::from(10)) == Number::from(300));
+    }
+
+    #[test]
+    fn sin_cos() {
+        for step in -32..=32 {
+            let angle = Number::from(step as f32 / 4.0);
+            let reference = f64::from(f32::from(angle));
+            let (sin, cos) = angle.sin_cos();
+
+            assert_near(sin, reference.sin());
+            assert_near(cos, reference.cos());
+        }
+    }
+
+    #[test]
+    fn atan2() {
+        for (y, x) in [
+            (0.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 0.0),
+            (1.0, -1.0),
+            (0.0, -1.0),
+            (-1.0, -1.0),
+            (-1.0, 0.0),
+            (-1.0, 1.0),
+            (3.0, 4.0),
+            (-120.0, 50.0),
+        ] {
+            assert_near(Number::from(y).atan2(Number::from(x)), f64::atan2(y, x));
+        }
+        assert!(Number::ZERO.atan2(Number::ZERO) == Number::ZERO);
+    }
+}
@@ -0,0 +1,173 @@
+use bevy::prelude::Entity;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::Location;
+
+use crate::core::maths::Number;
+
+#[cfg(all(feature = "overflow-saturating", feature = "overflow-wrapping"))]
+compile_error!("features \"overflow-saturating\" and \"overflow-wrapping\" are mutually exclusive");
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    Checked,
+    Saturating,
+    Wrapping,
+}
+
+// The policy is fixed at compile time so that every peer built from the same sources computes
+// the same results, in debug and release builds alike. Overflows panic unless a feature opts into
+// saturating or wrapping.
+#[cfg(not(any(feature = "overflow-saturating", feature = "overflow-wrapping")))]
+pub const OVERFLOW_POLICY: OverflowPolicy = OverflowPolicy::Checked;
+#[cfg(feature = "overflow-saturating")]
+pub const OVERFLOW_POLICY: OverflowPolicy = OverflowPolicy::Saturating;
+#[cfg(feature = "overflow-wrapping")]
+pub const OVERFLOW_POLICY: OverflowPolicy = OverflowPolicy::Wrapping;
+
+type OverflowContextValue = Option<(&'static str, Option<Entity>)>;
+
+thread_local! {
+    static OVERFLOW_CONTEXT: Cell<OverflowContextValue> = const { Cell::new(None) };
+}
+
+// Names the system and entity reported when an overflow happens, until dropped.
+// Only tracked when the "overflow-debug" feature is enabled.
+pub struct OverflowContext {
+    previous: OverflowContextValue,
+}
+
+impl OverflowContext {
+    pub fn enter(system: &'static str, entity: Option<Entity>) -> OverflowContext {
+        let previous = if cfg!(feature = "overflow-debug") {
+            OVERFLOW_CONTEXT.with(|context| context.replace(Some((system, entity))))
+        } else {
+            None
+        };
+
+        OverflowContext { previous }
+    }
+}
+
+impl Drop for OverflowContext {
+    fn drop(&mut self) {
+        if cfg!(feature = "overflow-debug") {
+            OVERFLOW_CONTEXT.with(|context| context.set(self.previous));
+        }
+    }
+}
+
+#[track_caller]
+fn report(error: fmt::Arguments) {
+    if cfg!(feature = "overflow-debug") {
+        let context = OVERFLOW_CONTEXT.with(|context| context.get());
+
+        match context {
+            Some((system, entity)) => log::warn!(
+                "{} at {} (system: {}, entity: {:?})",
+                error,
+                Location::caller(),
+                system,
+                entity
+            ),
+            None => log::warn!("{} at {}", error, Location::caller()),
+        }
+    }
+}
+
+#[track_caller]
+pub(crate) fn overflow<T>(
+    operation: &'static str,
+    saturated: impl FnOnce() -> T,
+    wrapped: impl FnOnce() -> T,
+) -> T {
+    report(format_args!("Number overflow in {}", operation));
+
+    match OVERFLOW_POLICY {
+        OverflowPolicy::Checked => {
+            panic!("Number overflow in {} at {}", operation, Location::caller())
+        }
+        OverflowPolicy::Saturating => saturated(),
+        OverflowPolicy::Wrapping => wrapped(),
+    }
+}
+
+// Saturates toward the sign of the dividend, wraps to zero.
+#[track_caller]
+pub(crate) fn division_by_zero(dividend: Number) -> Number {
+    report(format_args!("Number division by zero"));
+
+    match OVERFLOW_POLICY {
+        OverflowPolicy::Checked => panic!("Number division by zero at {}", Location::caller()),
+        OverflowPolicy::Saturating if dividend > Number::ZERO => Number::MAX,
+        OverflowPolicy::Saturating if dividend < Number::ZERO => Number::MIN,
+        OverflowPolicy::Saturating | OverflowPolicy::Wrapping => Number::ZERO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::{Number, OverflowPolicy, OVERFLOW_POLICY};
+
+    #[test]
+    fn in_range() {
+        assert!(Number::from(2) + Number::from(3) == Number::from(5));
+        assert!(Number::from(2) - Number::from(3) == Number::from(-1));
+        assert!(Number::from(2) * Number::from(3) == Number::from(6));
+        assert!(Number::from(3) / Number::from(2) == Number::from(1.5));
+        assert!(-Number::from(2) == Number::from(-2));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let result = std::panic::catch_unwind(|| {
+            (
+                Number::MAX + Number::DELTA,
+                Number::MIN - Number::DELTA,
+                Number::MAX * Number::from(2),
+                -Number::MIN,
+            )
+        });
+
+        match OVERFLOW_POLICY {
+            OverflowPolicy::Checked => assert!(result.is_err()),
+            OverflowPolicy::Saturating => {
+                assert!(result.unwrap() == (Number::MAX, Number::MIN, Number::MAX, Number::MAX))
+            }
+            OverflowPolicy::Wrapping => assert!(
+                result.unwrap()
+                    == (
+                        Number::MIN,
+                        Number::MAX,
+                        -Number::from(2) * Number::DELTA,
+                        Number::MIN
+                    )
+            ),
+        }
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let result = std::panic::catch_unwind(|| {
+            (
+                Number::ONE / Number::ZERO,
+                -Number::ONE / Number::ZERO,
+                Number::ZERO / Number::ZERO,
+            )
+        });
+
+        match OVERFLOW_POLICY {
+            OverflowPolicy::Checked => {
+                let error = result.unwrap_err();
+                let message = error.downcast_ref::<String>().unwrap();
+                assert!(message.starts_with("Number division by zero at"));
+            }
+            OverflowPolicy::Saturating => {
+                assert!(result.unwrap() == (Number::MAX, Number::MIN, Number::ZERO))
+            }
+            OverflowPolicy::Wrapping => {
+                assert!(result.unwrap() == (Number::ZERO, Number::ZERO, Number::ZERO))
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use bevy::prelude::Component;
+use bevy::reflect::{impl_reflect_value, ReflectDeserialize};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+use crate::core::maths::number::{FixedBits, FixedImpl};
+use crate::core::maths::Number;
+
+const SPLITMIX_INCREMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// Rollback random number generator resource, a SplitMix64 whose state is saved with the frame
+
+#[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq, Component, Serialize, Deserialize)]
+pub struct RollbackRng {
+    state: u64,
+}
+
+impl RollbackRng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(SPLITMIX_INCREMENT);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Returns a number in [0, 1)
+    pub fn next_number(&mut self) -> Number {
+        let bits = self.next_u64() >> (64 - FixedImpl::FRAC_NBITS);
+        Number(FixedImpl::from_bits(bits as FixedBits))
+    }
+
+    pub fn chance(&mut self, probability: Number) -> bool {
+        self.next_number() < probability
+    }
+
+    // Ranges return their start when empty and panic when inverted, like the ranges below
+    pub fn range_u32(&mut self, range: Range<u32>) -> u32 {
+        assert!(range.start <= range.end, "Inverted range {:?}", range);
+        range.start + self.below((range.end - range.start) as u128) as u32
+    }
+
+    pub fn range_i32(&mut self, range: Range<i32>) -> i32 {
+        assert!(range.start <= range.end, "Inverted range {:?}", range);
+        let span = range.end as i64 - range.start as i64;
+        (range.start as i64 + self.below(span as u128) as i64) as i32
+    }
+
+    pub fn range(&mut self, range: Range<Number>) -> Number {
+        assert!(range.start <= range.end, "Inverted range {:?}", range);
+        let start = range.start.0.to_bits() as i128;
+        let span = range.end.0.to_bits() as i128 - start;
+        let bits = start + self.below(span as u128) as i128;
+        Number(FixedImpl::from_bits(bits as FixedBits))
+    }
+
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        items.get(self.below(items.len() as u128) as usize)
+    }
+
+    // Returns an index picked proportionally to its weight, negative weights are never picked
+    pub fn weighted_index(&mut self, weights: &[Number]) -> Option<usize> {
+        let weight_bits = |weight: &Number| (weight.0.to_bits() as i128).max(0) as u128;
+        let total: u128 = weights.iter().map(weight_bits).sum();
+
+        if total == 0 {
+            return None;
+        }
+
+        let mut pick = self.below(total);
+        for (index, weight) in weights.iter().enumerate() {
+            let weight = weight_bits(weight);
+            if pick < weight {
+                return Some(index);
+            }
+            pick -= weight;
+        }
+        None
+    }
+
+    pub fn choose_weighted<'a, T>(&mut self, items: &'a [(T, Number)]) -> Option<&'a T> {
+        let weights = items.iter().map(|(_, weight)| *weight).collect::<Vec<_>>();
+        self.weighted_index(&weights).map(|index| &items[index].0)
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i as u128 + 1) as usize);
+        }
+    }
+
+    // Returns a value in [0, bound), or 0 when bound is 0. Weight totals can exceed 64 bits, those
+    // are rejection sampled on 128 bits.
+    fn below(&mut self, bound: u128) -> u128 {
+        if bound <= 1 << 64 {
+            return (self.next_u64() as u128 * bound) >> 64;
+        }
+
+        // 2^128 modulo bound, the values under it would make the lowest results more likely
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let value = (self.next_u64() as u128) << 64 | self.next_u64() as u128;
+            if value >= threshold {
+                return value % bound;
+            }
+        }
+    }
+}
+
+impl_reflect_value!(RollbackRng(Hash, Serialize, Deserialize));
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+
+    use crate::core::maths::{Number, RollbackRng};
+
+    #[test]
+    fn deterministic() {
+        let mut a = RollbackRng::from_seed(42);
+        let mut b = RollbackRng::from_seed(42);
+        let mut c = RollbackRng::from_seed(43);
+
+        let a_values = (0..8).map(|_| a.next_u64()).collect::<Vec<_>>();
+        assert!(a_values == (0..8).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert!(a_values != (0..8).map(|_| c.next_u64()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ranges() {
+        let mut rng = RollbackRng::from_seed(7);
+
+        for _ in 0..256 {
+            let number = rng.next_number();
+            assert!(number >= Number::ZERO && number < Number::ONE);
+
+            let number = rng.range(Number::from(-3)..Number::from(2));
+            assert!(number >= Number::from(-3) && number < Number::from(2));
+
+            assert!((-5..5).contains(&rng.range_i32(-5..5)));
+            assert!((10..12).contains(&rng.range_u32(10..12)));
+        }
+        assert!(rng.range_i32(3..3) == 3);
+        assert!(rng.choose::<u8>(&[]).is_none());
+
+        let (inverted_u32, inverted_i32) =
+            (Range { start: 3, end: 2 }, Range { start: 3, end: -2 });
+        assert!(
+            std::panic::catch_unwind(|| RollbackRng::default().range_u32(inverted_u32)).is_err()
+        );
+        assert!(
+            std::panic::catch_unwind(|| RollbackRng::default().range_i32(inverted_i32)).is_err()
+        );
+    }
+
+    #[test]
+    fn weighted() {
+        let mut rng = RollbackRng::from_seed(3);
+        let items = [('a', Number::ZERO), ('b', Number::ONE), ('c', -Number::ONE)];
+
+        for _ in 0..64 {
+            assert!(rng.choose_weighted(&items) == Some(&'b'));
+        }
+        assert!(rng.weighted_index(&[Number::ZERO]).is_none());
+
+        // Totals over 64 bits, only reachable with the widest numbers
+        let heavy = [u128::MAX / 3, 1 << 64, (1 << 64) + 1];
+        for bound in heavy {
+            for _ in 0..64 {
+                assert!(rng.below(bound) < bound);
+            }
+        }
+        let weights = [Number::MAX; 4];
+        for _ in 0..64 {
+            assert!(rng.weighted_index(&weights).unwrap() < 4);
+        }
+    }
+}
@@ -1,34 +1,293 @@
-use bevy::reflect::Reflect;
-use derive_more::*;
-use fixed::traits::ToFixed;
+use bevy::reflect::{FromReflect, Reflect};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-use crate::core::maths::Number;
+use crate::core::maths::{Angle, Number};
 
 // Reflect
-#[derive(Copy, Clone, Default, Reflect)]
+#[derive(Copy, Clone, Debug, Default, Reflect, FromReflect, Serialize, Deserialize)]
+#[reflect(Hash, PartialEq)]
 // Comparison
-#[derive(Eq, PartialOrd, Ord, PartialEq)]
-// Math operators
-#[derive(Add, AddAssign, Mul, MulAssign, Sub, SubAssign, Div, DivAssign, Neg)]
+#[derive(Eq, PartialOrd, Ord, PartialEq, Hash)]
 pub struct Vector2 {
     pub x: Number,
     pub y: Number,
 }
 
 impl Vector2 {
-    pub fn new<T: ToFixed>(x: T, y: T) -> Self {
+    pub const ZERO: Vector2 = Vector2::splat(Number::ZERO);
+    pub const ONE: Vector2 = Vector2::splat(Number::ONE);
+    pub const UP: Vector2 = Vector2 {
+        x: Number::ZERO,
+        y: Number::ONE,
+    };
+    pub const DOWN: Vector2 = Vector2 {
+        x: Number::ZERO,
+        y: Number(Number::ONE.0.wrapping_neg()),
+    };
+    pub const LEFT: Vector2 = Vector2 {
+        x: Number(Number::ONE.0.wrapping_neg()),
+        y: Number::ZERO,
+    };
+    pub const RIGHT: Vector2 = Vector2 {
+        x: Number::ONE,
+        y: Number::ZERO,
+    };
+
+    pub fn new<T: Into<Number>>(x: T, y: T) -> Self {
         Self {
             x: x.into(),
             y: y.into(),
         }
     }
+
+    pub const fn splat(value: Number) -> Self {
+        Self { x: value, y: value }
+    }
+
+    pub fn from_angle(angle: Angle) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self { x: cos, y: sin }
+    }
+
+    pub fn angle(self) -> Angle {
+        Angle::from_radians(self.y.atan2(self.x))
+    }
+
+    pub fn dot(self, other: Vector2) -> Number {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn cross(self, other: Vector2) -> Number {
+        self.x * other.y - self.y * other.x
+    }
+
+    // Overflows for long vectors at low integer precisions, prefer length or cmp_length.
+    pub fn length_squared(self) -> Number {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> Number {
+        self.x.hypot(self.y)
+    }
+
+    // Compares the length with a given length exactly, without computing a square root.
+    pub fn cmp_length(self, length: Number) -> Ordering {
+        if length < Number::ZERO {
+            return Ordering::Greater;
+        }
+
+        let x = i128::from(self.x.0.to_bits()).unsigned_abs();
+        let y = i128::from(self.y.0.to_bits()).unsigned_abs();
+        let length = i128::from(length.0.to_bits()).unsigned_abs();
+        (x * x + y * y).cmp(&(length * length))
+    }
+
+    pub fn distance_squared(self, other: Vector2) -> Number {
+        (other - self).length_squared()
+    }
+
+    pub fn distance(self, other: Vector2) -> Number {
+        (other - self).length()
+    }
+
+    // Returns the zero vector when the length is zero.
+    pub fn normalize(self) -> Vector2 {
+        let length = self.length();
+        if length == Number::ZERO {
+            Vector2::ZERO
+        } else {
+            self / length
+        }
+    }
+
+    pub fn perpendicular(self) -> Vector2 {
+        Vector2 {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+
+    pub fn rotate(self, angle: Angle) -> Vector2 {
+        let (sin, cos) = angle.sin_cos();
+        Vector2 {
+            x: self.x * cos - self.y * sin,
+            y: self.x * sin + self.y * cos,
+        }
+    }
+
+    // Returns the zero vector when projecting onto the zero vector.
+    pub fn project(self, onto: Vector2) -> Vector2 {
+        let direction = onto.normalize();
+        direction * self.dot(direction)
+    }
+
+    // Expects a normalized normal.
+    pub fn reflect(self, normal: Vector2) -> Vector2 {
+        self - normal * (self.dot(normal) * Number::from(2))
+    }
+
+    pub fn lerp(self, other: Vector2, t: Number) -> Vector2 {
+        self + (other - self) * t
+    }
+
+    pub fn abs(self) -> Vector2 {
+        Vector2 {
+            x: self.x.abs(),
+            y: self.y.abs(),
+        }
+    }
+
+    pub fn min(self, other: Vector2) -> Vector2 {
+        Vector2 {
+            x: self.x.min(other.x),
+            y: self.y.min(other.y),
+        }
+    }
+
+    pub fn max(self, other: Vector2) -> Vector2 {
+        Vector2 {
+            x: self.x.max(other.x),
+            y: self.y.max(other.y),
+        }
+    }
 }
 
-impl std::fmt::Debug for Vector2 {
+// Math operators, overflows are reported at the location of the caller
+
+macro_rules! impl_vector_operator {
+    ($trait:ident, $method:ident, $assign_trait:ident, $assign_method:ident, $rhs:ty, |$value:ident| $x:expr, $y:expr) => {
+        impl $trait<$rhs> for Vector2 {
+            type Output = Vector2;
+
+            #[track_caller]
+            fn $method(self, $value: $rhs) -> Vector2 {
+                Vector2 {
+                    x: $trait::$method(self.x, $x),
+                    y: $trait::$method(self.y, $y),
+                }
+            }
+        }
+        impl $assign_trait<$rhs> for Vector2 {
+            #[track_caller]
+            fn $assign_method(&mut self, rhs: $rhs) {
+                *self = $trait::$method(*self, rhs);
+            }
+        }
+    };
+}
+
+impl_vector_operator!(Add, add, AddAssign, add_assign, Vector2, |rhs| rhs.x, rhs.y);
+impl_vector_operator!(Sub, sub, SubAssign, sub_assign, Vector2, |rhs| rhs.x, rhs.y);
+impl_vector_operator!(Mul, mul, MulAssign, mul_assign, Number, |rhs| rhs, rhs);
+impl_vector_operator!(Div, div, DivAssign, div_assign, Number, |rhs| rhs, rhs);
+
+impl Mul<Vector2> for Number {
+    type Output = Vector2;
+
+    #[track_caller]
+    fn mul(self, vector: Vector2) -> Vector2 {
+        vector * self
+    }
+}
+impl Neg for Vector2 {
+    type Output = Vector2;
+
+    #[track_caller]
+    fn neg(self) -> Vector2 {
+        Vector2 {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
+impl std::fmt::Display for Vector2 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Vector2")
-            .field("x", &f32::from(self.x))
-            .field("y", &f32::from(self.y))
-            .finish()
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::core::maths::{Angle, Number, OverflowPolicy, Vector2, OVERFLOW_POLICY};
+
+    #[test]
+    fn products() {
+        let (a, b) = (Vector2::new(3, 4), Vector2::new(-2, 5));
+
+        assert!(a.dot(b) == Number::from(14));
+        assert!(a.cross(b) == Number::from(23));
+        assert!(a.length_squared() == Number::from(25));
+        assert!(a.length() == Number::from(5));
+        assert!(a.distance(Vector2::ZERO) == Number::from(5));
+        assert!(Number::from(2) * a == Vector2::new(6, 8));
+        assert!(a * Number::from(2) == Vector2::new(6, 8));
+    }
+
+    #[test]
+    fn long_vectors() {
+        let a = Vector2::new(3000, 4000);
+
+        assert!(a.length() == Number::from(5000));
+        assert!(a.cmp_length(Number::from(5000)) == Ordering::Equal);
+        assert!(a.cmp_length(Number::from(4999)) == Ordering::Greater);
+        assert!(a.cmp_length(Number::from(-1)) == Ordering::Greater);
+        assert!(Vector2::new(0, 300).normalize() == Vector2::UP);
+        assert!(Vector2::new(-20000, 0).normalize() == Vector2::LEFT);
+        assert!(a.project(Vector2::new(0, 400)) == Vector2::new(0, 4000));
+    }
+
+    #[test]
+    fn directions() {
+        assert!(Vector2::new(0, 8).normalize() == Vector2::UP);
+        assert!(Vector2::ZERO.normalize() == Vector2::ZERO);
+        assert!(Vector2::RIGHT.perpendicular() == Vector2::UP);
+        assert!(Vector2::UP.perpendicular() == Vector2::LEFT);
+        assert!(Vector2::RIGHT.rotate(Angle::from_radians(Number::PI)) == Vector2::LEFT);
+        assert!(Vector2::RIGHT.rotate(Angle::from_radians(Number::FRAC_PI_2)) == Vector2::UP);
+        assert!(Vector2::from_angle(Angle::from_radians(-Number::FRAC_PI_2)) == Vector2::DOWN);
+        assert!(Vector2::LEFT.angle() == Angle::from_radians(Number::PI));
+        assert!(Vector2::UP.angle() == Angle::from_radians(Number::FRAC_PI_2));
+    }
+
+    #[test]
+    fn projections() {
+        let velocity = Vector2::new(3, -4);
+
+        assert!(velocity.project(Vector2::RIGHT) == Vector2::new(3, 0));
+        assert!(velocity.project(Vector2::ZERO) == Vector2::ZERO);
+        assert!(velocity.reflect(Vector2::UP) == Vector2::new(3, 4));
+        assert!(Vector2::ZERO.lerp(velocity, Number::from(0.5)) == Vector2::new(1.5, -2.0));
+    }
+
+    #[test]
+    fn overflow_location() {
+        if OVERFLOW_POLICY != OverflowPolicy::Checked {
+            return;
+        }
+
+        let (line, error) = (
+            line!() + 1,
+            std::panic::catch_unwind(|| Vector2::splat(Number::MAX) + Vector2::ONE).unwrap_err(),
+        );
+        let message = error.downcast_ref::<String>().unwrap();
+
+        assert!(message.contains(&format!("{}:{}:", file!(), line)));
+    }
+
+    #[test]
+    fn formatting() {
+        let vector = Vector2::new(1.5, -2.0);
+
+        assert!(format!("{}", vector) == "(1.5, -2)");
+        assert!(format!("{:?}", vector) == "Vector2 { x: 1.5, y: -2 }");
+
+        let serialized = ron::to_string(&vector).unwrap();
+        assert!(serialized == r#"(x:"1.5",y:"-2")"#);
+        assert!(ron::from_str::<Vector2>(&serialized).unwrap() == vector);
     }
 }
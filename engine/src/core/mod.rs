@@ -6,33 +6,47 @@ use bevy::prelude::*;
 use bevy_ggrs::GGRSPlugin;
 use bevy_prototype_lyon::plugin::ShapePlugin;
 use ggrs::Config;
+use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 
+use crate::core::maths::RollbackRng;
 use crate::core::physics::*;
-use crate::core::transform::{sync_transform_system, Transform2};
+use crate::core::transform::*;
 use crate::game::game_scheduler;
 use crate::game::input::{game_input_system, GameInput};
+use crate::game::motion::{input_history_system, InputHistoryRes};
 
 #[derive(Eq, Hash, Clone, Debug, PartialEq, StageLabel)]
 enum RollbackStages {
+    Input,
     Game,
     Physics,
     TransformSynchronization,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct EngineConfig {
     pub window_title: String,
     pub window_width: f32,
     pub window_height: f32,
+    pub vsync: bool,
     pub update_frequency: usize,
+    // Fixed seed for replays and tests, P2P sessions negotiate a random one otherwise
+    pub rng_seed: Option<u64>,
+    // Starts the physics debug render, it can still be toggled with F1
+    pub physics_debug_render: bool,
 }
-impl EngineConfig {
-    pub fn default() -> EngineConfig {
+impl Default for EngineConfig {
+    fn default() -> EngineConfig {
         EngineConfig {
             window_title: "Rollback Engine".to_string(),
             window_width: 1280.0,
             window_height: 720.0,
+            vsync: true,
             update_frequency: 60,
+            rng_seed: None,
+            physics_debug_render: false,
         }
     }
 }
@@ -55,11 +69,18 @@ impl EngineApp for App {
             .with_input_system(game_input_system)
             .with_update_frequency(config.update_frequency)
             // components
+            .register_rollback_type::<Teleport>()
             .register_rollback_type::<Transform2>()
+            .register_rollback_type::<GlobalTransform2>()
+            .register_rollback_type::<RollbackFrameRes>()
+            .register_rollback_type::<TileMapCollider>()
             .register_rollback_type::<GravityRes>()
             .register_rollback_type::<JointSetRes>()
             .register_rollback_type::<CCDSolverRes>()
+            .register_rollback_type::<RollbackRng>()
+            .register_rollback_type::<InputHistoryRes>()
             .register_rollback_type::<KinematicBody>()
+            .register_rollback_type::<MovingPlatform>()
             .register_rollback_type::<BroadPhaseRes>()
             .register_rollback_type::<PhysicsHandle>()
             .register_rollback_type::<ColliderSetRes>()
@@ -73,20 +94,27 @@ impl EngineApp for App {
             // rollback scheduler
             .with_rollback_schedule(
                 Schedule::default()
-                    .with_stage(RollbackStages::Game, game_scheduler())
+                    .with_stage(
+                        RollbackStages::Input,
+                        SystemStage::single_threaded()
+                            .with_system(physics_system_rollback_entities)
+                            .with_system(physics_debug_rays_clear_system)
+                            .with_system(input_history_system),
+                    )
+                    .with_stage_after(
+                        RollbackStages::Input,
+                        RollbackStages::Game,
+                        game_scheduler(),
+                    )
                     .with_stage_after(
                         RollbackStages::Game,
                         RollbackStages::Physics,
-                        SystemStage::single_threaded()
-                            .with_system(physics_system_add)
-                            .with_system(physics_system_step)
-                            .with_system(physics_system_remove)
-                            .with_system(physics_system_kinematic),
+                        physics_stage(),
                     )
                     .with_stage_after(
                         RollbackStages::Physics,
                         RollbackStages::TransformSynchronization,
-                        SystemStage::parallel().with_system(sync_transform_system),
+                        transform_synchronization_stage(),
                     ),
             )
             //
@@ -96,16 +124,26 @@ impl EngineApp for App {
             // plugin
             .add_plugins(DefaultPlugins)
             .add_plugin(ShapePlugin)
+            .add_plugin(PhysicsDebugRenderPlugin)
+            .add_plugin(TransformSmoothingPlugin)
             // window
             .insert_resource(Msaa { samples: 4 })
             .insert_resource(WindowDescriptor {
                 title: config.window_title.to_owned(),
-                vsync: true,
+                vsync: config.vsync,
                 width: config.window_width,
                 height: config.window_height,
                 ..Default::default()
             })
             // resources
+            .insert_resource(PhysicsDebugRenderRes {
+                enabled: config.physics_debug_render,
+                ..Default::default()
+            })
+            .insert_resource(RollbackRng::from_seed(config.rng_seed.unwrap_or_default()))
+            .insert_resource(TransformSmoothingRes::from_update_frequency(
+                config.update_frequency,
+            ))
             .insert_resource(GravityRes::default())
             .insert_resource(JointSetRes::default())
             .insert_resource(CCDSolverRes::default())
@@ -115,8 +153,11 @@ impl EngineApp for App {
             .insert_resource(RigidBodySetRes::default())
             .insert_resource(IslandManagerRes::default())
             .insert_resource(QueryPipelineRes::default())
-            .insert_resource(IntegrationParametersRes::default())
+            .insert_resource(IntegrationParametersRes::from_update_frequency(
+                config.update_frequency,
+            ))
             .insert_resource(PhysicsHandleRemovedEntitiesRes::default())
+            .insert_resource(PhysicsRollbackEntitiesRes::default())
             // startup systems
             .add_startup_system_set_to_stage(
                 StartupStage::PostStartup,
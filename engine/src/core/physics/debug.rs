@@ -0,0 +1,518 @@
+use bevy::prelude::*;
+use bevy_prototype_lyon::prelude::*;
+use derive_more::{Deref, DerefMut};
+use rapier2d::prelude::*;
+
+use crate::core::physics::*;
+
+const PHYSICS_DEBUG_Z: f32 = 100.0;
+const PHYSICS_DEBUG_LINE_WIDTH: f32 = 1.0;
+const PHYSICS_DEBUG_BALL_SEGMENTS: usize = 16;
+const PHYSICS_DEBUG_CONTACT_SIZE: f32 = 2.0;
+const PHYSICS_DEBUG_NORMAL_LENGTH: f32 = 8.0;
+
+pub struct PhysicsDebugRenderPlugin;
+
+impl Plugin for PhysicsDebugRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PhysicsDebugRenderRes>()
+            .init_resource::<PhysicsDebugRaysRes>()
+            .add_startup_system(physics_debug_startup_system)
+            .add_system(physics_debug_render_system);
+    }
+}
+
+// Physics debug resources
+
+pub struct PhysicsDebugRenderRes {
+    pub enabled: bool,
+    pub toggle_key: Option<KeyCode>,
+    pub draw_colliders: bool,
+    pub draw_sensors: bool,
+    pub draw_contacts: bool,
+    pub draw_aabbs: bool,
+    pub draw_rays: bool,
+}
+
+impl Default for PhysicsDebugRenderRes {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            toggle_key: Some(KeyCode::F1),
+            draw_colliders: true,
+            draw_sensors: true,
+            draw_contacts: true,
+            draw_aabbs: false,
+            draw_rays: true,
+        }
+    }
+}
+
+// Rays cast during the last simulated frame, cleared when the next one starts. The kinematic
+// bodies record their casts, game systems can push theirs from the rollback schedule.
+pub struct PhysicsDebugRay {
+    pub ray: Ray,
+    pub max_toi: Real,
+    pub toi: Option<Real>,
+}
+
+#[derive(Default, Deref, DerefMut)]
+pub struct PhysicsDebugRaysRes(pub Vec<PhysicsDebugRay>);
+
+// Physics debug ECS components
+
+#[derive(Copy, Clone, Eq, PartialEq, Component)]
+pub enum PhysicsDebugLayer {
+    Static,
+    Kinematic,
+    Dynamic,
+    Sensor,
+    Aabb,
+    Contact,
+    RayHit,
+    RayMiss,
+}
+
+impl PhysicsDebugLayer {
+    const ALL: [PhysicsDebugLayer; 8] = [
+        PhysicsDebugLayer::Static,
+        PhysicsDebugLayer::Kinematic,
+        PhysicsDebugLayer::Dynamic,
+        PhysicsDebugLayer::Sensor,
+        PhysicsDebugLayer::Aabb,
+        PhysicsDebugLayer::Contact,
+        PhysicsDebugLayer::RayHit,
+        PhysicsDebugLayer::RayMiss,
+    ];
+
+    fn color(self) -> Color {
+        match self {
+            PhysicsDebugLayer::Static => Color::GREEN,
+            PhysicsDebugLayer::Kinematic => Color::CYAN,
+            PhysicsDebugLayer::Dynamic => Color::YELLOW,
+            PhysicsDebugLayer::Sensor => Color::PINK,
+            PhysicsDebugLayer::Aabb => Color::GRAY,
+            PhysicsDebugLayer::Contact => Color::RED,
+            PhysicsDebugLayer::RayHit => Color::ORANGE,
+            PhysicsDebugLayer::RayMiss => Color::WHITE,
+        }
+    }
+
+    fn from_body_type(body_type: RigidBodyType) -> Self {
+        match body_type {
+            RigidBodyType::Static => PhysicsDebugLayer::Static,
+            RigidBodyType::Dynamic => PhysicsDebugLayer::Dynamic,
+            RigidBodyType::KinematicPositionBased | RigidBodyType::KinematicVelocityBased => {
+                PhysicsDebugLayer::Kinematic
+            }
+        }
+    }
+}
+
+// Physics debug systems
+
+fn physics_debug_startup_system(mut commands: Commands) {
+    for layer in PhysicsDebugLayer::ALL {
+        commands
+            .spawn_bundle(GeometryBuilder::new().build(
+                DrawMode::Stroke(StrokeMode::new(layer.color(), PHYSICS_DEBUG_LINE_WIDTH)),
+                Transform::from_xyz(0.0, 0.0, PHYSICS_DEBUG_Z),
+            ))
+            .insert(layer);
+    }
+}
+
+// Runs first in the rollback schedule
+pub fn physics_debug_rays_clear_system(mut debug_rays: ResMut<PhysicsDebugRaysRes>) {
+    debug_rays.clear();
+}
+
+pub fn physics_debug_render_system(
+    keyboard_input: Res<Input<KeyCode>>,
+    collider_set: Res<ColliderSetRes>,
+    narrow_phase: Res<NarrowPhaseRes>,
+    rigid_body_set: Res<RigidBodySetRes>,
+    debug_rays: Res<PhysicsDebugRaysRes>,
+    mut debug_render: ResMut<PhysicsDebugRenderRes>,
+    //
+    mut query: Query<(&PhysicsDebugLayer, &mut Path, &mut Visibility)>,
+) {
+    if let Some(toggle_key) = debug_render.toggle_key {
+        if keyboard_input.just_pressed(toggle_key) {
+            debug_render.enabled = !debug_render.enabled;
+        }
+    }
+
+    if !debug_render.enabled {
+        for (_, _, mut visibility) in query.iter_mut() {
+            visibility.is_visible = false;
+        }
+        return;
+    }
+
+    let mut builders = PhysicsDebugLayer::ALL.map(|_| PathBuilder::new());
+
+    for (_, collider) in collider_set.iter() {
+        if collider.is_sensor() {
+            if debug_render.draw_sensors {
+                add_collider(&mut builders[PhysicsDebugLayer::Sensor as usize], collider);
+            }
+        } else if debug_render.draw_colliders {
+            let layer = collider
+                .parent()
+                .and_then(|rigid_body_handle| rigid_body_set.get(rigid_body_handle))
+                .map_or(PhysicsDebugLayer::Static, |rigid_body| {
+                    PhysicsDebugLayer::from_body_type(rigid_body.body_type())
+                });
+
+            add_collider(&mut builders[layer as usize], collider);
+        }
+        if debug_render.draw_aabbs {
+            let aabb = collider.compute_aabb();
+
+            add_polygon(
+                &mut builders[PhysicsDebugLayer::Aabb as usize],
+                &[
+                    Vec2::new(aabb.mins.x, aabb.mins.y),
+                    Vec2::new(aabb.maxs.x, aabb.mins.y),
+                    Vec2::new(aabb.maxs.x, aabb.maxs.y),
+                    Vec2::new(aabb.mins.x, aabb.maxs.y),
+                ],
+            );
+        }
+    }
+
+    if debug_render.draw_contacts {
+        let contact_builder = &mut builders[PhysicsDebugLayer::Contact as usize];
+
+        for contact_pair in narrow_phase.contact_pairs() {
+            for manifold in &contact_pair.manifolds {
+                let normal = Vec2::new(manifold.data.normal.x, manifold.data.normal.y);
+
+                for solver_contact in &manifold.data.solver_contacts {
+                    let point = Vec2::new(solver_contact.point.x, solver_contact.point.y);
+
+                    add_cross(contact_builder, point, PHYSICS_DEBUG_CONTACT_SIZE);
+                    add_line(
+                        contact_builder,
+                        point,
+                        point + normal * PHYSICS_DEBUG_NORMAL_LENGTH,
+                    );
+                }
+            }
+        }
+    }
+
+    if debug_render.draw_rays {
+        for debug_ray in debug_rays.iter() {
+            let origin = Vec2::new(debug_ray.ray.origin.x, debug_ray.ray.origin.y);
+            let dir = Vec2::new(debug_ray.ray.dir.x, debug_ray.ray.dir.y);
+
+            match debug_ray.toi {
+                Some(toi) => {
+                    let hit_builder = &mut builders[PhysicsDebugLayer::RayHit as usize];
+
+                    add_line(hit_builder, origin, origin + dir * toi);
+                    add_cross(hit_builder, origin + dir * toi, PHYSICS_DEBUG_CONTACT_SIZE);
+                }
+                None => add_line(
+                    &mut builders[PhysicsDebugLayer::RayMiss as usize],
+                    origin,
+                    origin + dir * debug_ray.max_toi,
+                ),
+            }
+        }
+    }
+
+    let mut builders = builders.map(Some);
+    for (layer, mut path, mut visibility) in query.iter_mut() {
+        if let Some(builder) = builders[*layer as usize].take() {
+            *path = builder.build();
+        }
+        visibility.is_visible = true;
+    }
+}
+
+// Physics debug shapes
+
+fn add_line(builder: &mut PathBuilder, from: Vec2, to: Vec2) {
+    builder.move_to(from);
+    builder.line_to(to);
+}
+
+fn add_cross(builder: &mut PathBuilder, center: Vec2, size: f32) {
+    add_line(
+        builder,
+        center - Vec2::new(size, size),
+        center + Vec2::new(size, size),
+    );
+    add_line(
+        builder,
+        center - Vec2::new(size, -size),
+        center + Vec2::new(size, -size),
+    );
+}
+
+fn add_polygon(builder: &mut PathBuilder, points: &[Vec2]) {
+    if let Some((first, rest)) = points.split_first() {
+        builder.move_to(*first);
+        for point in rest {
+            builder.line_to(*point);
+        }
+        builder.close();
+    }
+}
+
+fn add_circle(builder: &mut PathBuilder, center: Vec2, radius: f32) {
+    let points = (0..PHYSICS_DEBUG_BALL_SEGMENTS)
+        .map(|i| {
+            let angle = i as f32 * std::f32::consts::TAU / PHYSICS_DEBUG_BALL_SEGMENTS as f32;
+            center + Vec2::new(angle.cos(), angle.sin()) * radius
+        })
+        .collect::<Vec<_>>();
+
+    add_polygon(builder, &points);
+}
+
+fn add_collider(builder: &mut PathBuilder, collider: &Collider) {
+    let position = collider.position();
+    let to_world = |point: Point<Real>| {
+        let point = position * point;
+        Vec2::new(point.x, point.y)
+    };
+    let shape = collider.shape();
+
+    match shape.shape_type() {
+        ShapeType::Ball => {
+            let ball = shape.as_ball().unwrap();
+
+            add_circle(builder, to_world(Point::origin()), ball.radius);
+        }
+        ShapeType::Cuboid => {
+            let cuboid = shape.as_cuboid().unwrap();
+            let (hx, hy) = (cuboid.half_extents.x, cuboid.half_extents.y);
+
+            add_polygon(
+                builder,
+                &[
+                    to_world(point![-hx, -hy]),
+                    to_world(point![hx, -hy]),
+                    to_world(point![hx, hy]),
+                    to_world(point![-hx, hy]),
+                ],
+            );
+        }
+        ShapeType::Capsule => {
+            let capsule = shape.as_capsule().unwrap();
+            let (a, b) = (to_world(capsule.segment.a), to_world(capsule.segment.b));
+            let normal = (b - a).perp().normalize_or_zero() * capsule.radius;
+
+            add_line(builder, a + normal, b + normal);
+            add_line(builder, a - normal, b - normal);
+            add_circle(builder, a, capsule.radius);
+            add_circle(builder, b, capsule.radius);
+        }
+        ShapeType::Segment => {
+            let segment = shape.as_segment().unwrap();
+
+            add_line(builder, to_world(segment.a), to_world(segment.b));
+        }
+        ShapeType::Triangle => {
+            let triangle = shape.as_triangle().unwrap();
+
+            add_polygon(
+                builder,
+                &[
+                    to_world(triangle.a),
+                    to_world(triangle.b),
+                    to_world(triangle.c),
+                ],
+            );
+        }
+        ShapeType::ConvexPolygon => {
+            let convex_polygon = shape.as_convex_polygon().unwrap();
+            let points = convex_polygon
+                .points()
+                .iter()
+                .map(|point| to_world(*point))
+                .collect::<Vec<_>>();
+
+            add_polygon(builder, &points);
+        }
+        ShapeType::Polyline => {
+            let polyline = shape.as_polyline().unwrap();
+
+            for segment in polyline.segments() {
+                add_line(builder, to_world(segment.a), to_world(segment.b));
+            }
+        }
+        _ => (),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use bevy_prototype_lyon::prelude::*;
+    use rapier2d::prelude::*;
+
+    use crate::core::physics::debug::physics_debug_startup_system;
+    use crate::core::physics::*;
+
+    fn debug_world() -> (World, SystemStage) {
+        let mut world = World::new();
+        world.insert_resource(Input::<KeyCode>::default());
+        world.insert_resource(ColliderSetRes::default());
+        world.insert_resource(NarrowPhaseRes::default());
+        world.insert_resource(RigidBodySetRes::default());
+        world.insert_resource(PhysicsDebugRaysRes::default());
+        world.insert_resource(PhysicsDebugRenderRes::default());
+
+        SystemStage::single_threaded()
+            .with_system(physics_debug_startup_system)
+            .run(&mut world);
+
+        let stage = SystemStage::single_threaded().with_system(physics_debug_render_system);
+        (world, stage)
+    }
+
+    fn press_toggle(world: &mut World) {
+        let mut keyboard_input = world.get_resource_mut::<Input<KeyCode>>().unwrap();
+        keyboard_input.release(KeyCode::F1);
+        keyboard_input.clear();
+        keyboard_input.press(KeyCode::F1);
+    }
+
+    // Whether each layer is visible and has something to draw
+    fn layers(world: &mut World) -> Vec<(PhysicsDebugLayer, bool, bool)> {
+        let mut query = world.query::<(&PhysicsDebugLayer, &Path, &Visibility)>();
+        let mut layers = query
+            .iter(world)
+            .map(|(layer, path, visibility)| {
+                (*layer, visibility.is_visible, path.0.iter().count() > 0)
+            })
+            .collect::<Vec<_>>();
+        layers.sort_by_key(|(layer, _, _)| *layer as usize);
+        layers
+    }
+
+    fn drawn(world: &mut World) -> Vec<PhysicsDebugLayer> {
+        layers(world)
+            .into_iter()
+            .filter(|(_, _, drawn)| *drawn)
+            .map(|(layer, _, _)| layer)
+            .collect()
+    }
+
+    #[test]
+    fn toggle() {
+        let (mut world, mut stage) = debug_world();
+
+        stage.run(&mut world);
+        assert!(layers(&mut world).len() == PhysicsDebugLayer::ALL.len());
+        assert!(layers(&mut world).iter().all(|(_, visible, _)| !visible));
+
+        press_toggle(&mut world);
+        stage.run(&mut world);
+        assert!(
+            world
+                .get_resource::<PhysicsDebugRenderRes>()
+                .unwrap()
+                .enabled
+        );
+        assert!(layers(&mut world).iter().all(|(_, visible, _)| *visible));
+        assert!(drawn(&mut world).is_empty());
+
+        // Holding the key does not toggle it again
+        world.get_resource_mut::<Input<KeyCode>>().unwrap().clear();
+        stage.run(&mut world);
+        assert!(
+            world
+                .get_resource::<PhysicsDebugRenderRes>()
+                .unwrap()
+                .enabled
+        );
+
+        press_toggle(&mut world);
+        stage.run(&mut world);
+        assert!(
+            !world
+                .get_resource::<PhysicsDebugRenderRes>()
+                .unwrap()
+                .enabled
+        );
+        assert!(layers(&mut world).iter().all(|(_, visible, _)| !visible));
+    }
+
+    #[test]
+    fn layers_follow_bodies() {
+        let (mut world, mut stage) = debug_world();
+        world
+            .get_resource_mut::<PhysicsDebugRenderRes>()
+            .unwrap()
+            .enabled = true;
+
+        world.resource_scope(|world, mut rigid_body_set: Mut<RigidBodySetRes>| {
+            let mut collider_set = world.get_resource_mut::<ColliderSetRes>().unwrap();
+            let static_body = rigid_body_set.insert(RigidBodyBuilder::new_static().build());
+            let kinematic_body =
+                rigid_body_set.insert(RigidBodyBuilder::new_kinematic_velocity_based().build());
+
+            collider_set.insert_with_parent(
+                ColliderBuilder::cuboid(4.0, 1.0).build(),
+                static_body,
+                &mut rigid_body_set,
+            );
+            collider_set.insert_with_parent(
+                ColliderBuilder::ball(1.0).sensor(true).build(),
+                static_body,
+                &mut rigid_body_set,
+            );
+            collider_set.insert_with_parent(
+                ColliderBuilder::cuboid(1.0, 1.0).build(),
+                kinematic_body,
+                &mut rigid_body_set,
+            );
+        });
+        world
+            .get_resource_mut::<PhysicsDebugRaysRes>()
+            .unwrap()
+            .extend([
+                PhysicsDebugRay {
+                    ray: Ray::new(point![0.0, 4.0], vector![0.0, -1.0]),
+                    max_toi: 8.0,
+                    toi: Some(3.0),
+                },
+                PhysicsDebugRay {
+                    ray: Ray::new(point![0.0, 4.0], vector![0.0, 1.0]),
+                    max_toi: 8.0,
+                    toi: None,
+                },
+            ]);
+
+        stage.run(&mut world);
+        assert!(
+            drawn(&mut world)
+                == [
+                    PhysicsDebugLayer::Static,
+                    PhysicsDebugLayer::Kinematic,
+                    PhysicsDebugLayer::Sensor,
+                    PhysicsDebugLayer::RayHit,
+                    PhysicsDebugLayer::RayMiss,
+                ]
+        );
+
+        // Rays stay drawn until the next simulated frame clears them
+        stage.run(&mut world);
+        assert!(drawn(&mut world).contains(&PhysicsDebugLayer::RayHit));
+        SystemStage::single_threaded()
+            .with_system(physics_debug_rays_clear_system)
+            .run(&mut world);
+
+        let mut debug_render = world.get_resource_mut::<PhysicsDebugRenderRes>().unwrap();
+        debug_render.draw_colliders = false;
+        debug_render.draw_aabbs = true;
+        stage.run(&mut world);
+        assert!(drawn(&mut world) == [PhysicsDebugLayer::Sensor, PhysicsDebugLayer::Aabb]);
+    }
+}
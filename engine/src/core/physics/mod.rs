@@ -1,5 +1,7 @@
+mod debug;
 mod structs;
 mod systems;
 
+pub use debug::*;
 pub use structs::*;
 pub use systems::*;
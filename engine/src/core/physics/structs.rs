@@ -1,11 +1,13 @@
-use bevy::prelude::{Bundle, Component, Entity};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{Component, Entity, Query, Res};
 use bevy::reflect::{impl_reflect_value, Reflect, ReflectDeserialize};
+use bevy_ggrs::Rollback;
 use derive_more::{Deref, DerefMut};
 use rapier2d::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use crate::core::maths::Vector2;
+use crate::core::maths::{Angle, Number, Vector2};
 
 // Physics state resources
 
@@ -25,9 +27,9 @@ pub struct NarrowPhaseRes(pub NarrowPhase);
 pub struct RigidBodySetRes(pub RigidBodySet);
 #[derive(Clone, Deref, DerefMut, Component, Serialize, Deserialize)]
 pub struct IslandManagerRes(pub IslandManager);
-#[derive(Clone, Deref, DerefMut, Component, Serialize, Deserialize)]
+#[derive(Clone, Default, Deref, DerefMut, Component, Serialize, Deserialize)]
 pub struct QueryPipelineRes(pub QueryPipeline);
-#[derive(Clone, Deref, DerefMut, Component, Serialize, Deserialize)]
+#[derive(Clone, Default, Deref, DerefMut, Component, Serialize, Deserialize)]
 pub struct IntegrationParametersRes(pub IntegrationParameters);
 
 impl Default for GravityRes {
@@ -70,14 +72,12 @@ impl Default for IslandManagerRes {
         Self(IslandManager::new())
     }
 }
-impl Default for QueryPipelineRes {
-    fn default() -> Self {
-        Self(QueryPipeline::default())
-    }
-}
-impl Default for IntegrationParametersRes {
-    fn default() -> Self {
-        Self(IntegrationParameters::default())
+impl IntegrationParametersRes {
+    pub fn from_update_frequency(update_frequency: usize) -> Self {
+        Self(IntegrationParameters {
+            dt: 1.0 / update_frequency as f32,
+            ..Default::default()
+        })
     }
 }
 impl_reflect_value!(GravityRes(Serialize, Deserialize));
@@ -110,31 +110,364 @@ pub struct PhysicsCollider {
     pub layer_mask: u32,
 }
 
+#[derive(Clone, Default, Reflect, Component)]
+pub struct Teleport {
+    pub pos: Vector2,
+    pub rotation: Angle,
+    pub wake_up: bool,
+    pub reset_velocity: bool,
+}
+
+impl Teleport {
+    pub fn from_pos(pos: Vector2) -> Self {
+        Self {
+            pos,
+            wake_up: true,
+            ..Default::default()
+        }
+    }
+    pub fn from_pos_rotation(pos: Vector2, rotation: Angle) -> Self {
+        Self {
+            pos,
+            rotation,
+            wake_up: true,
+            ..Default::default()
+        }
+    }
+}
+
 // Physics ECS bodies components
 
 #[derive(Clone, Default, Reflect, Component)]
 pub struct StaticBody {}
 
-#[derive(Clone, Default, Reflect, Component)]
+#[derive(Clone, Reflect, Component)]
 pub struct KinematicBody {
+    // Units per second, integrated over the physics time step
     pub velocity: Vector2,
+    pub max_slope: Angle,
+    pub step_height: Number,
+    pub snap_distance: Number,
     pub(crate) is_on_wall: bool,
     pub(crate) is_on_floor: bool,
     pub(crate) is_on_ceiling: bool,
+    pub(crate) floor_handle: PhysicsHandle,
+}
+
+impl Default for KinematicBody {
+    fn default() -> Self {
+        Self {
+            velocity: Vector2::default(),
+            max_slope: Angle::from_degrees(Number::from(45)),
+            step_height: Number::default(),
+            snap_distance: Number::default(),
+            is_on_wall: false,
+            is_on_floor: false,
+            is_on_ceiling: false,
+            floor_handle: PhysicsHandle::default(),
+        }
+    }
 }
 
-#[derive(Bundle)]
-pub struct StaticBodyBundle {
-    pub body: StaticBody,
-    pub handle: PhysicsHandle,
-    pub collider: PhysicsCollider,
+#[derive(Clone, Default, Reflect, Component)]
+pub struct MovingPlatform {
+    pub points: Vec<Vector2>,
+    // Units per second
+    pub speed: Number,
+    pub(crate) target: usize,
+}
+
+// The bevy 0.6 Bundle derive forgets its fields once they are moved into the world, the lint is
+// reported on the generated impls which only an allow on their enclosing module reaches
+#[allow(clippy::forget_non_drop)]
+mod bundles {
+    use bevy::prelude::Bundle;
+
+    use crate::core::physics::{
+        KinematicBody, PhysicsCollider, PhysicsHandle, StaticBody, TileMapCollider,
+    };
+
+    #[derive(Bundle)]
+    pub struct StaticBodyBundle {
+        pub body: StaticBody,
+        pub handle: PhysicsHandle,
+        pub collider: PhysicsCollider,
+    }
+
+    #[derive(Default, Bundle)]
+    pub struct KinematicBodyBundle {
+        pub body: KinematicBody,
+        pub handle: PhysicsHandle,
+        pub collider: PhysicsCollider,
+    }
+
+    #[derive(Default, Bundle)]
+    pub struct TileMapBodyBundle {
+        pub body: StaticBody,
+        pub handle: PhysicsHandle,
+        pub tile_map: TileMapCollider,
+    }
+}
+
+pub use bundles::*;
+
+// Physics ECS tile map components
+
+pub const TILE_EMPTY: u8 = 0;
+pub const TILE_SOLID: u8 = 1;
+pub const TILE_ONE_WAY: u8 = 2;
+
+// Tiles are stored row by row, row 0 being the bottom row, starting at the entity position.
+#[derive(Clone, Default, Reflect, Component)]
+pub struct TileMapCollider {
+    width: usize,
+    height: usize,
+    tiles: Vec<u8>,
+    pub tile_size: Vector2,
+    pub layer: u32,
+    pub layer_mask: u32,
+    // What the colliders were built from, rolled back along with them
+    built_width: usize,
+    built_tiles: Vec<u8>,
+    built_tile_size: Vector2,
 }
 
-#[derive(Default, Bundle)]
-pub struct KinematicBodyBundle {
-    pub body: KinematicBody,
-    pub handle: PhysicsHandle,
-    pub collider: PhysicsCollider,
+impl TileMapCollider {
+    pub fn new(width: usize, height: usize, tile_size: Vector2) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![TILE_EMPTY; width * height],
+            tile_size,
+            layer: u32::MAX,
+            layer_mask: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    // Returns None unless there are exactly width * height tiles
+    pub fn from_tiles(
+        width: usize,
+        height: usize,
+        tiles: Vec<u8>,
+        tile_size: Vector2,
+    ) -> Option<Self> {
+        if tiles.len() != width.checked_mul(height)? {
+            return None;
+        }
+
+        Some(Self {
+            width,
+            height,
+            tiles,
+            tile_size,
+            layer: u32::MAX,
+            layer_mask: u32::MAX,
+            ..Default::default()
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn tiles(&self) -> &[u8] {
+        &self.tiles
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then(|| y * self.width + x)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<u8> {
+        self.index(x, y)
+            .and_then(|index| self.tiles.get(index))
+            .copied()
+    }
+
+    // Returns the replaced tile, or None when out of bounds
+    pub fn set(&mut self, x: usize, y: usize, tile: u8) -> Option<u8> {
+        let index = self.index(x, y)?;
+        self.tiles
+            .get_mut(index)
+            .map(|previous| std::mem::replace(previous, tile))
+    }
+
+    // Whether the colliders match the tiles, a change of layers only needs them to be patched
+    pub(crate) fn is_built(&self) -> bool {
+        self.built_width == self.width
+            && self.built_tiles == self.tiles
+            && self.built_tile_size == self.tile_size
+    }
+
+    pub(crate) fn set_built(&mut self) {
+        self.built_width = self.width;
+        self.built_tiles = self.tiles.clone();
+        self.built_tile_size = self.tile_size;
+    }
+
+    fn is_solid(&self, x: isize, y: isize) -> bool {
+        x >= 0 && y >= 0 && self.get(x as usize, y as usize) == Some(TILE_SOLID)
+    }
+
+    // Traces the boundary of the solid tiles into closed loops of tile corners, counter-clockwise
+    // around solid tiles and without collinear corners, so that neighbouring tiles share no seam.
+    pub fn solid_outlines(&self) -> Vec<Vec<(usize, usize)>> {
+        // Unit edges with the solid tile on their left, keyed by their start corner
+        let mut edges: BTreeMap<(usize, usize), Vec<TileEdge>> = BTreeMap::new();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if !self.is_solid(x as isize, y as isize) {
+                    continue;
+                }
+
+                for edge in TileEdge::ALL {
+                    let (dx, dy) = edge.outside();
+                    if !self.is_solid(x as isize + dx, y as isize + dy) {
+                        edges.entry(edge.start(x, y)).or_default().push(edge);
+                    }
+                }
+            }
+        }
+
+        let mut outlines = Vec::new();
+
+        while let Some((&start, _)) = edges.iter().next() {
+            let mut corners = Vec::new();
+            let mut corner = start;
+            let mut previous: Option<TileEdge> = None;
+
+            loop {
+                let outgoing = edges.get_mut(&corner).unwrap();
+                // Where two loops touch diagonally, turning left keeps them apart
+                let index = match previous {
+                    Some(previous) => [previous.left(), previous, previous.right()]
+                        .iter()
+                        .find_map(|edge| outgoing.iter().position(|other| other == edge))
+                        .unwrap(),
+                    None => 0,
+                };
+                let edge = outgoing.remove(index);
+                if outgoing.is_empty() {
+                    edges.remove(&corner);
+                }
+
+                if previous != Some(edge) {
+                    corners.push(corner);
+                }
+                previous = Some(edge);
+                corner = edge.end(corner);
+
+                if corner == start {
+                    break;
+                }
+            }
+
+            // The start corner is the lowest of the leftmost corners, always a turn
+            outlines.push(corners);
+        }
+
+        outlines
+    }
+
+    // Merges horizontally adjacent one-way tiles into (x, y, width) runs.
+    pub fn one_way_runs(&self) -> Vec<(usize, usize, usize)> {
+        let mut runs = Vec::new();
+
+        for y in 0..self.height {
+            let mut x = 0;
+            while x < self.width {
+                if self.get(x, y) != Some(TILE_ONE_WAY) {
+                    x += 1;
+                    continue;
+                }
+
+                let mut width = 1;
+                while self.get(x + width, y) == Some(TILE_ONE_WAY) {
+                    width += 1;
+                }
+                runs.push((x, y, width));
+                x += width;
+            }
+        }
+
+        runs
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum TileEdge {
+    Bottom,
+    Right,
+    Top,
+    Left,
+}
+
+impl TileEdge {
+    const ALL: [TileEdge; 4] = [Self::Bottom, Self::Right, Self::Top, Self::Left];
+
+    fn outside(self) -> (isize, isize) {
+        match self {
+            Self::Bottom => (0, -1),
+            Self::Right => (1, 0),
+            Self::Top => (0, 1),
+            Self::Left => (-1, 0),
+        }
+    }
+
+    fn start(self, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            Self::Bottom => (x, y),
+            Self::Right => (x + 1, y),
+            Self::Top => (x + 1, y + 1),
+            Self::Left => (x, y + 1),
+        }
+    }
+
+    fn end(self, (x, y): (usize, usize)) -> (usize, usize) {
+        match self {
+            Self::Bottom => (x + 1, y),
+            Self::Right => (x, y + 1),
+            Self::Top => (x - 1, y),
+            Self::Left => (x, y - 1),
+        }
+    }
+
+    fn left(self) -> Self {
+        Self::ALL[(self as usize + 1) % 4]
+    }
+
+    fn right(self) -> Self {
+        Self::ALL[(self as usize + 3) % 4]
+    }
+}
+
+// Physics hooks
+
+pub fn is_one_way_collider(collider: &Collider) -> bool {
+    collider
+        .active_hooks()
+        .contains(ActiveHooks::MODIFY_SOLVER_CONTACTS)
+}
+
+pub struct OneWayPlatformHooks;
+
+impl PhysicsHooks<RigidBodySet, ColliderSet> for OneWayPlatformHooks {
+    fn modify_solver_contacts(
+        &self,
+        context: &mut ContactModificationContext<RigidBodySet, ColliderSet>,
+    ) {
+        if is_one_way_collider(&context.colliders[context.collider1]) {
+            context.update_as_oneway_platform(&Vector::y(), 0.1);
+        } else if is_one_way_collider(&context.colliders[context.collider2]) {
+            context.update_as_oneway_platform(&-Vector::y(), 0.1);
+        }
+    }
 }
 
 // Physics ECS components book-keeping
@@ -143,3 +476,255 @@ pub struct KinematicBodyBundle {
 pub struct PhysicsHandleRemovedEntitiesRes(pub HashMap<Entity, RigidBodyHandle>);
 
 impl_reflect_value!(PhysicsHandleRemovedEntitiesRes(Serialize, Deserialize));
+
+// Physics ECS entities lookup
+
+const PHYSICS_ENTITY_USER_DATA_FLAG: u128 = 1 << 64;
+const PHYSICS_ROLLBACK_USER_DATA_FLAG: u128 = 1 << 65;
+
+// Rollback entities are identified by their rollback id, loading a frame matches entities by
+// rollback id and respawns the ones despawned since with new entity ids
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PhysicsEntityId {
+    Entity(Entity),
+    Rollback(u32),
+}
+
+impl PhysicsEntityId {
+    pub fn new(entity: Entity, rollback: Option<&Rollback>) -> Self {
+        match rollback {
+            Some(rollback) => Self::Rollback(rollback.id()),
+            None => Self::Entity(entity),
+        }
+    }
+
+    pub fn from_user_data(user_data: u128) -> Option<Self> {
+        if user_data & PHYSICS_ROLLBACK_USER_DATA_FLAG != 0 {
+            Some(Self::Rollback(user_data as u32))
+        } else if user_data & PHYSICS_ENTITY_USER_DATA_FLAG != 0 {
+            Some(Self::Entity(Entity::from_bits(user_data as u64)))
+        } else {
+            None
+        }
+    }
+
+    pub fn to_user_data(self) -> u128 {
+        match self {
+            Self::Entity(entity) => PHYSICS_ENTITY_USER_DATA_FLAG | entity.to_bits() as u128,
+            Self::Rollback(id) => PHYSICS_ROLLBACK_USER_DATA_FLAG | id as u128,
+        }
+    }
+}
+
+// Rollback ids of the rollback entities, rebuilt at the start of every frame
+#[derive(Clone, Default, Deref, DerefMut)]
+pub struct PhysicsRollbackEntitiesRes(pub HashMap<u32, Entity>);
+
+#[derive(SystemParam)]
+pub struct PhysicsEntities<'w, 's> {
+    collider_set: Res<'w, ColliderSetRes>,
+    rigid_body_set: Res<'w, RigidBodySetRes>,
+    rollback_entities: Res<'w, PhysicsRollbackEntitiesRes>,
+    physics_handles: Query<'w, 's, &'static PhysicsHandle>,
+}
+
+impl<'w, 's> PhysicsEntities<'w, 's> {
+    pub fn rigid_body_entity(&self, rigid_body_handle: RigidBodyHandle) -> Option<Entity> {
+        self.rigid_body_set
+            .get(rigid_body_handle)
+            .and_then(|rigid_body| self.user_data_entity(rigid_body.user_data))
+    }
+
+    pub fn collider_entity(&self, collider_handle: ColliderHandle) -> Option<Entity> {
+        self.collider_set
+            .get(collider_handle)
+            .and_then(|collider| self.user_data_entity(collider.user_data))
+    }
+
+    fn user_data_entity(&self, user_data: u128) -> Option<Entity> {
+        match PhysicsEntityId::from_user_data(user_data)? {
+            PhysicsEntityId::Entity(entity) => Some(entity),
+            PhysicsEntityId::Rollback(id) => self.rollback_entities.get(&id).copied(),
+        }
+    }
+
+    pub fn entity_rigid_body(&self, entity: Entity) -> Option<RigidBodyHandle> {
+        self.physics_handles
+            .get(entity)
+            .ok()
+            .map(|physics_handle| physics_handle.0)
+            .filter(|rigid_body_handle| self.rigid_body_set.contains(*rigid_body_handle))
+    }
+
+    pub fn entity_colliders(&self, entity: Entity) -> &[ColliderHandle] {
+        self.entity_rigid_body(entity)
+            .map(|rigid_body_handle| self.rigid_body_set[rigid_body_handle].colliders())
+            .unwrap_or(&[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+    use bevy::prelude::{Entity, Stage, SystemStage, World};
+    use bevy_ggrs::Rollback;
+    use rapier2d::prelude::*;
+
+    use crate::core::maths::Vector2;
+    use crate::core::physics::{
+        physics_system_rollback_entities, ColliderSetRes, PhysicsEntities, PhysicsEntityId,
+        PhysicsHandle, PhysicsRollbackEntitiesRes, RigidBodySetRes, TileMapCollider, TILE_EMPTY,
+        TILE_ONE_WAY, TILE_SOLID,
+    };
+
+    fn tile_map(rows: &[&str]) -> TileMapCollider {
+        // Rows are written top to bottom, as they appear on screen
+        let tiles = rows
+            .iter()
+            .rev()
+            .flat_map(|row| row.chars())
+            .map(|tile| match tile {
+                '#' => TILE_SOLID,
+                '-' => TILE_ONE_WAY,
+                _ => TILE_EMPTY,
+            })
+            .collect();
+
+        TileMapCollider::from_tiles(rows[0].len(), rows.len(), tiles, Vector2::ONE).unwrap()
+    }
+
+    #[test]
+    fn tile_map_bounds() {
+        let mut tile_map = TileMapCollider::new(2, 3, Vector2::ONE);
+
+        assert!(tile_map.set(1, 2, TILE_SOLID) == Some(TILE_EMPTY));
+        assert!(tile_map.get(1, 2) == Some(TILE_SOLID));
+        assert!(tile_map.get(2, 0).is_none());
+        assert!(tile_map.get(0, 3).is_none());
+        assert!(tile_map.set(2, 0, TILE_SOLID).is_none());
+        assert!(
+            tile_map
+                .tiles()
+                .iter()
+                .filter(|&&tile| tile == TILE_SOLID)
+                .count()
+                == 1
+        );
+
+        assert!(TileMapCollider::from_tiles(2, 3, vec![TILE_EMPTY; 5], Vector2::ONE).is_none());
+        assert!(TileMapCollider::from_tiles(2, 3, vec![TILE_EMPTY; 6], Vector2::ONE).is_some());
+    }
+
+    #[test]
+    fn tile_map_outlines() {
+        let l_shape = tile_map(&["#.", "##"]);
+        assert!(l_shape.solid_outlines() == [vec![(0, 0), (2, 0), (2, 1), (1, 1), (1, 2), (0, 2)]]);
+
+        let staircase = tile_map(&["#..", "##.", "###"]);
+        assert!(
+            staircase.solid_outlines()
+                == [vec![
+                    (0, 0),
+                    (3, 0),
+                    (3, 1),
+                    (2, 1),
+                    (2, 2),
+                    (1, 2),
+                    (1, 3),
+                    (0, 3)
+                ]]
+        );
+
+        let diagonal = tile_map(&[".#", "#."]);
+        assert!(
+            diagonal.solid_outlines()
+                == [
+                    vec![(0, 0), (1, 0), (1, 1), (0, 1)],
+                    vec![(1, 1), (2, 1), (2, 2), (1, 2)]
+                ]
+        );
+
+        let ring = tile_map(&["###", "#.#", "###"]);
+        assert!(
+            ring.solid_outlines()
+                == [
+                    vec![(0, 0), (3, 0), (3, 3), (0, 3)],
+                    vec![(1, 1), (1, 2), (2, 2), (2, 1)]
+                ]
+        );
+
+        assert!(tile_map(&["--", "##"]).solid_outlines().len() == 1);
+    }
+
+    #[test]
+    fn tile_map_one_way_runs() {
+        let tile_map = tile_map(&["-#--", "--.-"]);
+
+        assert!(tile_map.one_way_runs() == [(0, 0, 2), (3, 0, 1), (0, 1, 1), (2, 1, 2)]);
+    }
+
+    #[test]
+    fn user_data() {
+        let entity = Entity::from_raw(42);
+        let entity_id = PhysicsEntityId::new(entity, None);
+        let rollback_id = PhysicsEntityId::new(entity, Some(&Rollback::new(7)));
+
+        assert!(entity_id == PhysicsEntityId::Entity(entity));
+        assert!(rollback_id == PhysicsEntityId::Rollback(7));
+        assert!(PhysicsEntityId::from_user_data(entity_id.to_user_data()) == Some(entity_id));
+        assert!(PhysicsEntityId::from_user_data(rollback_id.to_user_data()) == Some(rollback_id));
+        assert!(PhysicsEntityId::from_user_data(0).is_none());
+        assert!(PhysicsEntityId::from_user_data(42).is_none());
+    }
+
+    #[test]
+    fn entities_lookup() {
+        let mut world = World::new();
+        let mut rigid_body_set = RigidBodySetRes::default();
+        let mut collider_set = ColliderSetRes::default();
+
+        let user_data = PhysicsEntityId::Rollback(7).to_user_data();
+        let rigid_body_handle =
+            rigid_body_set.insert(RigidBodyBuilder::new_static().user_data(user_data).build());
+        let collider_handle = collider_set.insert_with_parent(
+            ColliderBuilder::ball(1.0).user_data(user_data).build(),
+            rigid_body_handle,
+            &mut rigid_body_set,
+        );
+        world.insert_resource(rigid_body_set);
+        world.insert_resource(collider_set);
+        world.insert_resource(PhysicsRollbackEntitiesRes::default());
+
+        let entity = world
+            .spawn()
+            .insert(Rollback::new(7))
+            .insert(PhysicsHandle(rigid_body_handle))
+            .id();
+        let mut state = SystemState::<PhysicsEntities>::new(&mut world);
+        let mut stage =
+            SystemStage::single_threaded().with_system(physics_system_rollback_entities);
+
+        stage.run(&mut world);
+        let physics_entities = state.get_mut(&mut world);
+        assert!(physics_entities.rigid_body_entity(rigid_body_handle) == Some(entity));
+        assert!(physics_entities.collider_entity(collider_handle) == Some(entity));
+        assert!(physics_entities.entity_rigid_body(entity) == Some(rigid_body_handle));
+        assert!(physics_entities.entity_colliders(entity) == [collider_handle]);
+
+        // Loading a frame respawns the rollback entities despawned since with new ids
+        world.despawn(entity);
+        let respawned = world
+            .spawn()
+            .insert(Rollback::new(7))
+            .insert(PhysicsHandle(rigid_body_handle))
+            .id();
+        assert!(respawned != entity);
+
+        stage.run(&mut world);
+        let physics_entities = state.get_mut(&mut world);
+        assert!(physics_entities.rigid_body_entity(rigid_body_handle) == Some(respawned));
+        assert!(physics_entities.collider_entity(collider_handle) == Some(respawned));
+        assert!(physics_entities.entity_rigid_body(entity).is_none());
+        assert!(physics_entities.entity_colliders(respawned) == [collider_handle]);
+    }
+}
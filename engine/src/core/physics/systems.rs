@@ -1,7 +1,11 @@
 use bevy::prelude::*;
-use bevy_prototype_lyon::prelude::*;
+use bevy_ggrs::Rollback;
+use rapier2d::parry::query::{self, Contact, TOIStatus, TOI};
 use rapier2d::prelude::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
 
+use crate::core::maths::{Angle, Number, OverflowContext};
 use crate::core::physics::*;
 use crate::core::transform::Transform2;
 
@@ -9,76 +13,148 @@ fn scale_physics<T>(value: T) -> T {
     value
 }
 
+type TileMapQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut PhysicsHandle,
+        &'static mut TileMapCollider,
+        &'static Transform2,
+    ),
+    Or<(Changed<TileMapCollider>, Changed<Transform2>)>,
+>;
+
+// Loading a frame marks the tile maps as changed, their colliders are only rebuilt when the tiles
+// differ from the ones they were built from so that peers keep identical collider sets
+pub fn physics_system_tile_map(
+    mut collider_set: ResMut<ColliderSetRes>,
+    mut rigid_body_set: ResMut<RigidBodySetRes>,
+    mut island_manager: ResMut<IslandManagerRes>,
+    mut query_pipeline: ResMut<QueryPipelineRes>,
+    //
+    rollbacks: Query<&Rollback>,
+    mut query: TileMapQuery,
+) {
+    let mut rebuilt = false;
+
+    for (entity, mut handle, mut tile_map, transform2) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("physics_system_tile_map", Some(entity));
+
+        let position = Isometry::new(
+            transform2.pos.into(),
+            transform2.rotation.radians().to_f32(),
+        );
+        let collision_groups = InteractionGroups::new(tile_map.layer, tile_map.layer_mask);
+
+        if let Some(rigid_body) = rigid_body_set.get_mut(handle.0) {
+            // The physics step writes Transform2 back every frame, only move on actual changes
+            if *rigid_body.position() != position {
+                rigid_body.set_position(position, true);
+            }
+            if tile_map.is_built() {
+                for collider_handle in rigid_body.colliders() {
+                    let collider = &mut collider_set[*collider_handle];
+                    if collider.collision_groups() != collision_groups {
+                        collider.set_collision_groups(collision_groups);
+                    }
+                }
+                continue;
+            }
+        } else {
+            let user_data = PhysicsEntityId::new(entity, rollbacks.get(entity).ok()).to_user_data();
+            let body = RigidBodyBuilder::new_static()
+                .position(position)
+                .user_data(user_data)
+                .build();
+
+            handle.0 = rigid_body_set.insert(body);
+        }
+
+        let user_data = rigid_body_set[handle.0].user_data;
+        let previous_colliders = rigid_body_set[handle.0].colliders().to_vec();
+        for collider_handle in previous_colliders {
+            collider_set.remove(
+                collider_handle,
+                &mut island_manager,
+                &mut rigid_body_set,
+                true,
+            );
+        }
+
+        let tile_width = tile_map.tile_size.x.to_f32();
+        let tile_height = tile_map.tile_size.y.to_f32();
+
+        for outline in tile_map.solid_outlines() {
+            let vertices = outline
+                .iter()
+                .map(|&(x, y)| point![x as f32 * tile_width, y as f32 * tile_height])
+                .collect();
+            let indices = (0..outline.len() as u32)
+                .map(|index| [index, (index + 1) % outline.len() as u32])
+                .collect();
+            let collider = ColliderBuilder::polyline(vertices, Some(indices))
+                .restitution(0.0)
+                .collision_groups(collision_groups)
+                .user_data(user_data)
+                .build();
+
+            collider_set.insert_with_parent(collider, handle.0, &mut rigid_body_set);
+        }
+        for (x, y, width) in tile_map.one_way_runs() {
+            let top = (y + 1) as f32 * tile_height;
+            let collider = ColliderBuilder::segment(
+                point![x as f32 * tile_width, top],
+                point![(x + width) as f32 * tile_width, top],
+            )
+            .restitution(0.0)
+            .active_hooks(ActiveHooks::MODIFY_SOLVER_CONTACTS)
+            .collision_groups(collision_groups)
+            .user_data(user_data)
+            .build();
+
+            collider_set.insert_with_parent(collider, handle.0, &mut rigid_body_set);
+        }
+
+        tile_map.set_built();
+        rebuilt = true;
+    }
+
+    // The kinematic bodies cast against the new colliders before the physics step
+    if rebuilt {
+        query_pipeline.update(&island_manager, &rigid_body_set, &collider_set);
+    }
+}
+
+// Runs first in the rollback schedule, after a frame may have been loaded
+pub fn physics_system_rollback_entities(
+    mut rollback_entities: ResMut<PhysicsRollbackEntitiesRes>,
+    //
+    query: Query<(Entity, &Rollback)>,
+) {
+    rollback_entities.clear();
+    rollback_entities.extend(
+        query
+            .iter()
+            .map(|(entity, rollback)| (rollback.id(), entity)),
+    );
+}
+
 pub fn physics_system_add(
-    collider_set: ResMut<ColliderSetRes>,
-    rigid_body_set: ResMut<RigidBodySetRes>,
-    mut commands: Commands,
+    mut collider_set: ResMut<ColliderSetRes>,
+    mut rigid_body_set: ResMut<RigidBodySetRes>,
     mut rigid_body_entities: ResMut<PhysicsHandleRemovedEntitiesRes>,
     //
+    rollbacks: Query<&Rollback>,
     mut query: Query<(Entity, &PhysicsHandle), Added<PhysicsHandle>>,
 ) {
     for (entity, physics_handle) in query.iter_mut() {
-        if rigid_body_set.contains(physics_handle.0) {
-            let rigid_body = &rigid_body_set[physics_handle.0];
+        if let Some(rigid_body) = rigid_body_set.get_mut(physics_handle.0) {
+            let user_data = PhysicsEntityId::new(entity, rollbacks.get(entity).ok()).to_user_data();
+            rigid_body.user_data = user_data;
 
             for collider_handle in rigid_body.colliders() {
-                let rigid_body_collider = &collider_set[*collider_handle];
-                let rigid_body_collider_shape = rigid_body_collider.shape();
-
-                match rigid_body_collider_shape.shape_type() {
-                    ShapeType::Ball => {
-                        let ball = rigid_body_collider_shape.as_ball().unwrap();
-                        let circle_shape = shapes::Circle {
-                            radius: scale_physics(ball.radius),
-                            ..Default::default()
-                        };
-
-                        commands.entity(entity).with_children(|child_builder| {
-                            child_builder.spawn_bundle(GeometryBuilder::build_as(
-                                &circle_shape,
-                                DrawMode::Outlined {
-                                    fill_mode: FillMode {
-                                        color: Color::YELLOW,
-                                        options: FillOptions::default(),
-                                    },
-                                    outline_mode: StrokeMode {
-                                        color: Color::BLACK,
-                                        options: StrokeOptions::default(),
-                                    },
-                                },
-                                Transform::default(), // TODO: collider transform
-                            ));
-                        });
-                    }
-                    ShapeType::Cuboid => {
-                        let cuboid = rigid_body_collider_shape.as_cuboid().unwrap();
-                        let rectangle_shape = shapes::Rectangle {
-                            extents: Vec2::new(
-                                scale_physics(cuboid.half_extents[0] * 2.0),
-                                scale_physics(cuboid.half_extents[1] * 2.0),
-                            ),
-                            ..Default::default()
-                        };
-
-                        commands.entity(entity).with_children(|child_builder| {
-                            child_builder.spawn_bundle(GeometryBuilder::build_as(
-                                &rectangle_shape,
-                                DrawMode::Outlined {
-                                    fill_mode: FillMode {
-                                        color: Color::YELLOW,
-                                        options: FillOptions::default(),
-                                    },
-                                    outline_mode: StrokeMode {
-                                        color: Color::BLACK,
-                                        options: StrokeOptions::default(),
-                                    },
-                                },
-                                Transform::default(), // TODO: collider transform
-                            ));
-                        });
-                    }
-                    _ => (),
-                };
+                collider_set[*collider_handle].user_data = user_data;
             }
 
             rigid_body_entities.insert(entity, physics_handle.0);
@@ -86,6 +162,44 @@ pub fn physics_system_add(
     }
 }
 
+pub fn physics_system_teleport(
+    mut commands: Commands,
+    mut rigid_body_set: ResMut<RigidBodySetRes>,
+    //
+    mut query: Query<(
+        Entity,
+        &Teleport,
+        &PhysicsHandle,
+        Option<&mut KinematicBody>,
+    )>,
+) {
+    for (entity, teleport, physics_handle, kinematic_body) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("physics_system_teleport", Some(entity));
+
+        if let Some(rigid_body) = rigid_body_set.get_mut(physics_handle.0) {
+            rigid_body.set_position(
+                Isometry::new(teleport.pos.into(), teleport.rotation.radians().to_f32()),
+                teleport.wake_up,
+            );
+
+            if teleport.reset_velocity {
+                rigid_body.set_linvel(vector![0.0, 0.0], teleport.wake_up);
+                rigid_body.set_angvel(0.0, teleport.wake_up);
+
+                if let Some(mut kinematic_body) = kinematic_body {
+                    kinematic_body.velocity = Default::default();
+                }
+            }
+            if teleport.wake_up {
+                rigid_body.wake_up(true);
+            }
+        }
+
+        commands.entity(entity).remove::<Teleport>();
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn physics_system_step(
     gravity: Res<GravityRes>,
     integration_parameters: Res<IntegrationParametersRes>,
@@ -99,9 +213,9 @@ pub fn physics_system_step(
     mut island_manager: ResMut<IslandManagerRes>,
     mut query_pipeline: ResMut<QueryPipelineRes>,
     //
-    mut query: Query<(&mut Transform2, &PhysicsHandle)>,
+    mut query: Query<(Entity, &mut Transform2, &PhysicsHandle)>,
 ) {
-    let hooks = ();
+    let hooks = OneWayPlatformHooks;
     let events = ();
     let mut physics_pipeline = PhysicsPipeline::new();
 
@@ -121,15 +235,17 @@ pub fn physics_system_step(
 
     query_pipeline.update(&island_manager, &rigid_body_set, &collider_set);
 
-    for (mut transform2, physics_handle) in query.iter_mut() {
+    for (entity, mut transform2, physics_handle) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("physics_system_step", Some(entity));
+
         if rigid_body_set.contains(physics_handle.0) {
             let rigid_body = &rigid_body_set[physics_handle.0];
             let rigid_body_rotation = rigid_body.rotation();
             let rigid_body_translation = rigid_body.translation();
 
-            transform2.pos.x = scale_physics(rigid_body_translation.x).into();
-            transform2.pos.y = scale_physics(rigid_body_translation.y).into();
-            transform2.rotation = rigid_body_rotation.angle().into();
+            transform2.pos = scale_physics(*rigid_body_translation).into();
+            transform2.rotation =
+                Angle::from_radians(Number::from_f32(rigid_body_rotation.angle()));
         }
     }
 }
@@ -157,34 +273,962 @@ pub fn physics_system_remove(
     }
 }
 
-pub fn physics_system_kinematic(// mut collider_set: ResMut<ColliderSetRes>,
-    // mut rigid_body_set: ResMut<RigidBodySetRes>,
-    // //
-    // mut query: Query<(&mut PhysicsHandle, &PhysicsCollider, &Transform2), With<KinematicBody>>,
+pub fn physics_system_moving_platform(
+    integration_parameters: Res<IntegrationParametersRes>,
+    //
+    mut query: Query<(Entity, &mut MovingPlatform, &mut KinematicBody, &Transform2)>,
+) {
+    let tick_rate = Number::from(integration_parameters.dt.recip().round() as i32);
+
+    for (entity, mut moving_platform, mut kinematic_body, transform2) in query.iter_mut() {
+        if moving_platform.points.is_empty() {
+            continue;
+        }
+
+        let _overflow_context =
+            OverflowContext::enter("physics_system_moving_platform", Some(entity));
+
+        let target_index = moving_platform.target % moving_platform.points.len();
+        let target = moving_platform.points[target_index];
+        let delta = target - transform2.pos;
+        let distance = delta.length();
+
+        // Speeds are in units per second, a platform within one tick of its target lands on it
+        if distance * tick_rate <= moving_platform.speed {
+            kinematic_body.velocity = delta * tick_rate;
+            moving_platform.target = (target_index + 1) % moving_platform.points.len();
+        } else {
+            kinematic_body.velocity = delta * (moving_platform.speed / distance);
+        }
+    }
+}
+
+pub fn physics_system_kinematic(
+    collider_set: Res<ColliderSetRes>,
+    query_pipeline: Res<QueryPipelineRes>,
+    integration_parameters: Res<IntegrationParametersRes>,
+    debug_render: Option<Res<PhysicsDebugRenderRes>>,
+    //
+    mut rigid_body_set: ResMut<RigidBodySetRes>,
+    mut debug_rays: Option<ResMut<PhysicsDebugRaysRes>>,
+    //
+    mut query: Query<(
+        Entity,
+        &PhysicsHandle,
+        &mut KinematicBody,
+        Option<&MovingPlatform>,
+    )>,
 ) {
-    // for (mut handle, collider, transform2) in query.iter_mut() {
-    //     if let Some(mut rigid_body) = rigid_body_set.get(handle.0) {}
-    // }
+    let mut platform_displacements = HashMap::new();
+    // The casts are only recorded while the debug render draws them
+    let recorded_rays = debug_render
+        .filter(|debug_render| debug_render.enabled && debug_render.draw_rays)
+        .and(debug_rays.as_ref())
+        .map(|_| RefCell::new(Vec::new()));
+
+    let dt = integration_parameters.dt;
+
+    for (entity, handle, kinematic_body, moving_platform) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("physics_system_kinematic", Some(entity));
+
+        if let (Some(_), Some(rigid_body)) = (moving_platform, rigid_body_set.get_mut(handle.0)) {
+            let velocity: Vector<Real> = kinematic_body.velocity.into();
+
+            rigid_body.set_linvel(velocity, true);
+            platform_displacements.insert(handle.0, velocity * dt);
+        }
+    }
+
+    for (entity, handle, mut kinematic_body, moving_platform) in query.iter_mut() {
+        if moving_platform.is_some() {
+            continue;
+        }
+
+        let _overflow_context = OverflowContext::enter("physics_system_kinematic", Some(entity));
+
+        let (rigid_body, collider) = match rigid_body_set.get(handle.0).and_then(|rigid_body| {
+            let collider_handle = rigid_body.colliders().first()?;
+            Some((rigid_body, &collider_set[*collider_handle]))
+        }) {
+            Some(body_and_collider) => body_and_collider,
+            None => continue,
+        };
+        let kinematic_move = KinematicMove {
+            shape: collider.shape(),
+            groups: collider.collision_groups(),
+            collider_set: &collider_set,
+            query_pipeline: &query_pipeline,
+            step_height: kinematic_body.step_height.to_f32(),
+            floor_normal_y: kinematic_body.max_slope.cos().to_f32(),
+            debug_rays: recorded_rays.as_ref(),
+        };
+        let rigid_body_handle = handle.0;
+        // The query pipeline may still hold colliders removed since the last physics step
+        let filter = |collider_handle: ColliderHandle| {
+            collider_set
+                .get(collider_handle)
+                .is_some_and(|collider| collider.parent() != Some(rigid_body_handle))
+        };
+        // Colliders only follow their body on the physics step, after a teleport for instance
+        let start = rigid_body.position()
+            * collider
+                .position_wrt_parent()
+                .copied()
+                .unwrap_or_else(Isometry::identity);
+        let velocity: Vector<Real> = kinematic_body.velocity.into();
+        let was_on_floor = kinematic_body.is_on_floor;
+        let mut position = start;
+        let mut collisions = Vec::new();
+
+        kinematic_move.depenetrate(&filter, &mut position);
+        kinematic_move.move_and_slide(
+            &filter,
+            &mut position,
+            velocity * dt,
+            was_on_floor,
+            &mut collisions,
+        );
+
+        let snap_distance = kinematic_body.snap_distance.to_f32();
+        if was_on_floor && velocity.y <= 0.0 && snap_distance > 0.0 {
+            kinematic_move.snap_to_floor(&filter, &mut position, snap_distance);
+        }
+
+        kinematic_body.is_on_wall = false;
+        kinematic_body.is_on_floor = false;
+        kinematic_body.is_on_ceiling = false;
+        for collision in &collisions {
+            if kinematic_move.is_floor(collision.normal) {
+                kinematic_body.is_on_floor = true;
+            } else if kinematic_move.is_ceiling(collision.normal) {
+                kinematic_body.is_on_ceiling = true;
+            } else {
+                kinematic_body.is_on_wall = true;
+            }
+        }
+
+        let floor = kinematic_move
+            .floor(&filter, &position, KINEMATIC_FLOOR_PROBE)
+            .and_then(|collider_handle| collider_set[collider_handle].parent());
+        kinematic_body.is_on_floor |= floor.is_some();
+        kinematic_body.floor_handle = PhysicsHandle(floor.unwrap_or_else(RigidBodyHandle::invalid));
+
+        if let Some(displacement) = floor.and_then(|floor| platform_displacements.get(&floor)) {
+            let carry_filter = |collider_handle: ColliderHandle| {
+                filter(collider_handle) && collider_set[collider_handle].parent() != floor
+            };
+
+            kinematic_move.move_and_slide(
+                &carry_filter,
+                &mut position,
+                *displacement,
+                false,
+                &mut Vec::new(),
+            );
+        }
+
+        let displacement = position.translation.vector - start.translation.vector;
+        rigid_body_set[handle.0].set_linvel(displacement / dt, true);
+    }
+
+    if let (Some(debug_rays), Some(recorded_rays)) = (debug_rays.as_mut(), recorded_rays) {
+        debug_rays.extend(recorded_rays.into_inner());
+    }
+}
+
+// Kinematic movement
+
+const KINEMATIC_SKIN: f32 = 0.01;
+const KINEMATIC_MAX_SLIDES: usize = 4;
+const KINEMATIC_FLOOR_PROBE: f32 = 0.1;
+
+struct KinematicCollision {
+    normal: Vector<Real>,
+}
+
+struct KinematicMove<'a> {
+    shape: &'a dyn Shape,
+    groups: InteractionGroups,
+    collider_set: &'a ColliderSet,
+    query_pipeline: &'a QueryPipeline,
+    step_height: Real,
+    floor_normal_y: Real,
+    debug_rays: Option<&'a RefCell<Vec<PhysicsDebugRay>>>,
+}
+
+impl<'a> KinematicMove<'a> {
+    fn is_floor(&self, normal: Vector<Real>) -> bool {
+        normal.y >= self.floor_normal_y
+    }
+
+    fn is_ceiling(&self, normal: Vector<Real>) -> bool {
+        normal.y <= -self.floor_normal_y
+    }
+
+    // Records the path of the shape center as a debug ray
+    fn cast(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &Isometry<Real>,
+        motion: Vector<Real>,
+    ) -> Option<(ColliderHandle, TOI)> {
+        let hit = self.cast_shape(filter, position, motion);
+
+        if let Some(debug_rays) = self.debug_rays {
+            debug_rays.borrow_mut().push(PhysicsDebugRay {
+                ray: Ray::new(position.translation.vector.into(), motion),
+                max_toi: 1.0,
+                toi: hit.map(|(_, toi)| toi.toi),
+            });
+        }
+        hit
+    }
+
+    fn cast_shape(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &Isometry<Real>,
+        motion: Vector<Real>,
+    ) -> Option<(ColliderHandle, TOI)> {
+        // one-way colliders only block falling bodies
+        let is_falling = motion.y < 0.0;
+        let mut ignored = Vec::new();
+
+        loop {
+            let cast_filter = |collider_handle: ColliderHandle| {
+                filter(collider_handle)
+                    && !ignored.contains(&collider_handle)
+                    && (is_falling || !is_one_way_collider(&self.collider_set[collider_handle]))
+            };
+            let (collider_handle, mut toi) = self.query_pipeline.cast_shape(
+                self.collider_set,
+                position,
+                &motion,
+                self.shape,
+                1.0,
+                self.groups,
+                Some(&cast_filter),
+            )?;
+
+            if toi.status != TOIStatus::Penetrating {
+                return Some((collider_handle, toi));
+            }
+
+            // A body already overlapping a collider is stopped at once and slides along the
+            // contact normal, as the normals of a cast starting inside a collider are unreliable.
+            // It can still move away from it, and through one-way colliders it is overlapping.
+            let collider = &self.collider_set[collider_handle];
+            let normal = query::contact(
+                position,
+                self.shape,
+                collider.position(),
+                collider.shape(),
+                0.0,
+            )
+            .ok()
+            .flatten()
+            .map(|contact| contact.normal2);
+
+            match normal {
+                Some(normal) if motion.dot(&normal) < 0.0 && !is_one_way_collider(collider) => {
+                    toi.toi = 0.0;
+                    toi.normal1 = normal;
+                    return Some((collider_handle, toi));
+                }
+                _ => ignored.push(collider_handle),
+            }
+        }
+    }
+
+    // Pushes the body out of the solid colliders it overlaps, as a cast starting inside a collider
+    // ignores the rest of it
+    fn depenetrate(&self, filter: &dyn Fn(ColliderHandle) -> bool, position: &mut Isometry<Real>) {
+        let solid_filter = |collider_handle: ColliderHandle| {
+            filter(collider_handle) && !is_one_way_collider(&self.collider_set[collider_handle])
+        };
+
+        for _ in 0..KINEMATIC_MAX_SLIDES {
+            let mut deepest: Option<Contact> = None;
+
+            self.query_pipeline.intersections_with_shape(
+                self.collider_set,
+                position,
+                self.shape,
+                self.groups,
+                Some(&solid_filter),
+                |collider_handle| {
+                    let collider = &self.collider_set[collider_handle];
+                    let contact = query::contact(
+                        position,
+                        self.shape,
+                        collider.position(),
+                        collider.shape(),
+                        0.0,
+                    );
+                    if let Ok(Some(contact)) = contact {
+                        if deepest.is_none_or(|deepest| contact.dist < deepest.dist) {
+                            deepest = Some(contact);
+                        }
+                    }
+                    true
+                },
+            );
+
+            match deepest {
+                Some(contact) => {
+                    position.translation.vector +=
+                        contact.normal2.into_inner() * (KINEMATIC_SKIN - contact.dist);
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn move_and_slide(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &mut Isometry<Real>,
+        mut motion: Vector<Real>,
+        can_step: bool,
+        collisions: &mut Vec<KinematicCollision>,
+    ) {
+        for _ in 0..KINEMATIC_MAX_SLIDES {
+            let distance = motion.norm();
+            if distance <= KINEMATIC_SKIN {
+                break;
+            }
+
+            match self.cast(filter, position, motion) {
+                Some((_, toi)) => {
+                    let direction = motion / distance;
+                    let travel = (toi.toi * distance - KINEMATIC_SKIN).max(0.0);
+                    let normal = toi.normal1.into_inner();
+                    let remaining = motion - direction * travel;
+
+                    position.translation.vector += direction * travel;
+
+                    if !self.is_floor(normal) && !self.is_ceiling(normal) {
+                        if let Some(stepped) = can_step
+                            .then(|| self.step(filter, position, remaining))
+                            .flatten()
+                        {
+                            *position = stepped;
+                            motion = vector![0.0, remaining.y];
+                            continue;
+                        }
+                    }
+
+                    motion = remaining - normal * remaining.dot(&normal);
+                    // slopes steeper than the max slope act as walls
+                    if !self.is_floor(normal) && remaining.y <= 0.0 {
+                        motion.y = motion.y.min(0.0);
+                    }
+                    collisions.push(KinematicCollision { normal });
+                }
+                None => {
+                    position.translation.vector += motion;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn step(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &Isometry<Real>,
+        motion: Vector<Real>,
+    ) -> Option<Isometry<Real>> {
+        let up = vector![0.0, self.step_height];
+        let forward = vector![motion.x, 0.0];
+        if self.step_height <= 0.0
+            || forward.norm() <= KINEMATIC_SKIN
+            || self.cast(filter, position, up).is_some()
+        {
+            return None;
+        }
+
+        let mut stepped = *position;
+        stepped.translation.vector += up;
+        if self.cast(filter, &stepped, forward).is_some() {
+            return None;
+        }
+
+        stepped.translation.vector += forward;
+        let down = vector![0.0, -self.step_height];
+        match self.cast(filter, &stepped, down) {
+            Some((_, toi)) if self.is_floor(toi.normal1.into_inner()) => {
+                stepped.translation.vector.y -=
+                    (toi.toi * self.step_height - KINEMATIC_SKIN).max(0.0);
+                Some(stepped)
+            }
+            _ => None,
+        }
+    }
+
+    fn snap_to_floor(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &mut Isometry<Real>,
+        distance: Real,
+    ) {
+        if let Some((_, toi)) = self.cast(filter, position, vector![0.0, -distance]) {
+            if self.is_floor(toi.normal1.into_inner()) {
+                position.translation.vector.y -= (toi.toi * distance - KINEMATIC_SKIN).max(0.0);
+            }
+        }
+    }
+
+    fn floor(
+        &self,
+        filter: &dyn Fn(ColliderHandle) -> bool,
+        position: &Isometry<Real>,
+        distance: Real,
+    ) -> Option<ColliderHandle> {
+        self.cast(filter, position, vector![0.0, -distance])
+            .filter(|(_, toi)| self.is_floor(toi.normal1.into_inner()))
+            .map(|(collider_handle, _)| collider_handle)
+    }
 }
 
 pub fn physics_startup_system_kinematic_register(
     mut collider_set: ResMut<ColliderSetRes>,
     mut rigid_body_set: ResMut<RigidBodySetRes>,
     //
-    mut query: Query<(&mut PhysicsHandle, &PhysicsCollider, &Transform2), With<KinematicBody>>,
+    rollbacks: Query<&Rollback>,
+    mut query: Query<
+        (Entity, &mut PhysicsHandle, &PhysicsCollider, &Transform2),
+        With<KinematicBody>,
+    >,
 ) {
-    for (mut handle, collider, transform2) in query.iter_mut() {
+    for (entity, mut handle, collider, transform2) in query.iter_mut() {
+        let user_data = PhysicsEntityId::new(entity, rollbacks.get(entity).ok()).to_user_data();
         let body = RigidBodyBuilder::new_kinematic_velocity_based()
-            .rotation(transform2.rotation.into())
-            .translation(vector![transform2.pos.x.into(), transform2.pos.y.into()])
+            .rotation(transform2.rotation.radians().to_f32())
+            .translation(transform2.pos.into())
+            .user_data(user_data)
             .build();
         let body_handle = rigid_body_set.insert(body);
-        let body_collider = ColliderBuilder::cuboid(collider.size.x.into(), collider.size.y.into())
-            .restitution(0.0)
-            .collision_groups(InteractionGroups::new(collider.layer, collider.layer_mask))
-            .build();
+        let body_collider =
+            ColliderBuilder::cuboid(collider.size.x.to_f32(), collider.size.y.to_f32())
+                .restitution(0.0)
+                .collision_groups(InteractionGroups::new(collider.layer, collider.layer_mask))
+                .user_data(user_data)
+                .build();
 
         handle.0 = body_handle;
         collider_set.insert_with_parent(body_collider, body_handle, &mut rigid_body_set);
     }
 }
+
+// Physics stage, whose systems run in declaration order rather than in the arbitrary order bevy
+// picks for unordered systems
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, SystemLabel)]
+pub enum PhysicsSystem {
+    TileMap,
+    Add,
+    Teleport,
+    MovingPlatform,
+    Kinematic,
+    Step,
+    Remove,
+}
+
+pub fn physics_stage() -> SystemStage {
+    SystemStage::single_threaded()
+        .with_system(physics_system_tile_map.label(PhysicsSystem::TileMap))
+        .with_system(
+            physics_system_add
+                .label(PhysicsSystem::Add)
+                .after(PhysicsSystem::TileMap),
+        )
+        .with_system(
+            physics_system_teleport
+                .label(PhysicsSystem::Teleport)
+                .after(PhysicsSystem::Add),
+        )
+        .with_system(
+            physics_system_moving_platform
+                .label(PhysicsSystem::MovingPlatform)
+                .after(PhysicsSystem::Teleport),
+        )
+        .with_system(
+            physics_system_kinematic
+                .label(PhysicsSystem::Kinematic)
+                .after(PhysicsSystem::MovingPlatform),
+        )
+        .with_system(
+            physics_system_step
+                .label(PhysicsSystem::Step)
+                .after(PhysicsSystem::Kinematic),
+        )
+        .with_system(
+            physics_system_remove
+                .label(PhysicsSystem::Remove)
+                .after(PhysicsSystem::Step),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use rapier2d::prelude::*;
+
+    use crate::core::maths::{Number, Vector2};
+    use crate::core::physics::*;
+    use crate::core::transform::Transform2;
+
+    fn physics_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(GravityRes::default());
+        world.insert_resource(JointSetRes::default());
+        world.insert_resource(CCDSolverRes::default());
+        world.insert_resource(BroadPhaseRes::default());
+        world.insert_resource(ColliderSetRes::default());
+        world.insert_resource(NarrowPhaseRes::default());
+        world.insert_resource(RigidBodySetRes::default());
+        world.insert_resource(IslandManagerRes::default());
+        world.insert_resource(QueryPipelineRes::default());
+        world.insert_resource(IntegrationParametersRes::default());
+        world.insert_resource(PhysicsHandleRemovedEntitiesRes::default());
+        world.insert_resource(PhysicsRollbackEntitiesRes::default());
+        world
+    }
+
+    fn run(stage: &mut SystemStage, world: &mut World) {
+        stage.run(world);
+        world.clear_trackers();
+    }
+
+    fn run_frames(stage: &mut SystemStage, world: &mut World, frames: usize) {
+        for _ in 0..frames {
+            run(stage, world);
+        }
+    }
+
+    // Rows are written top to bottom, solid tiles are #, with a tile size of one
+    fn spawn_tile_map(world: &mut World, rows: &[&str]) -> Entity {
+        let mut tile_map = TileMapCollider::new(rows[0].len(), rows.len(), Vector2::ONE);
+        for (y, row) in rows.iter().rev().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                if tile == '#' {
+                    tile_map.set(x, y, TILE_SOLID);
+                }
+            }
+        }
+
+        world
+            .spawn()
+            .insert_bundle(TileMapBodyBundle {
+                tile_map,
+                ..Default::default()
+            })
+            .insert(Transform2::default())
+            .id()
+    }
+
+    fn spawn_kinematic(
+        world: &mut World,
+        pos: Vector2,
+        size: Vector2,
+        body: KinematicBody,
+    ) -> Entity {
+        world
+            .spawn()
+            .insert_bundle(KinematicBodyBundle {
+                body,
+                collider: PhysicsCollider {
+                    size,
+                    layer: u32::MAX,
+                    layer_mask: u32::MAX,
+                },
+                ..Default::default()
+            })
+            .insert(Transform2::from_pos(pos))
+            .id()
+    }
+
+    // Registers the bodies and runs a first frame to fill the query pipeline, bodies should only
+    // be given a velocity afterwards
+    fn start(world: &mut World) -> SystemStage {
+        let mut startup_stage =
+            SystemStage::single_threaded().with_system(physics_startup_system_kinematic_register);
+        run(&mut startup_stage, world);
+
+        let mut stage = physics_stage();
+        run(&mut stage, world);
+        stage
+    }
+
+    fn set_velocity(world: &mut World, entity: Entity, velocity: Vector2) {
+        world.get_mut::<KinematicBody>(entity).unwrap().velocity = velocity;
+    }
+
+    fn pos(world: &World, entity: Entity) -> Vector2 {
+        world.get::<Transform2>(entity).unwrap().pos
+    }
+
+    fn near(value: Number, expected: f32) -> bool {
+        (value.to_f32() - expected).abs() <= 0.1
+    }
+
+    // A right-angled triangle standing on a floor of height one, rising to the right for a positive
+    // slope and to the left for a negative one
+    fn spawn_ramp(world: &mut World, x: f32, length: f32, degrees: f32) {
+        let height = length * degrees.abs().to_radians().tan();
+        let apex = if degrees > 0.0 { x + length } else { x };
+        let collider = ColliderBuilder::triangle(
+            point![x, 1.0],
+            point![x + length, 1.0],
+            point![apex, 1.0 + height],
+        )
+        .build();
+
+        world.resource_scope(|world, mut rigid_body_set: Mut<RigidBodySetRes>| {
+            let mut collider_set = world.get_resource_mut::<ColliderSetRes>().unwrap();
+            let handle = rigid_body_set.insert(RigidBodyBuilder::new_static().build());
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+        });
+    }
+
+    fn body_on_floor(world: &mut World, x: f32, y: f32, body: KinematicBody) -> Entity {
+        let pos = Vector2 {
+            x: Number::from_f32(x),
+            y: Number::from_f32(y),
+        };
+        spawn_kinematic(world, pos, Vector2::splat(0.5.into()), body)
+    }
+
+    #[test]
+    fn tile_map_transform() {
+        let mut world = physics_world();
+        let mut stage = physics_stage();
+
+        let mut tile_map = TileMapCollider::new(2, 1, Vector2::ONE);
+        tile_map.set(0, 0, TILE_SOLID);
+        let entity = world
+            .spawn()
+            .insert_bundle(TileMapBodyBundle {
+                tile_map,
+                ..Default::default()
+            })
+            .insert(Transform2::from_pos(Vector2::new(1, 2)))
+            .id();
+        run(&mut stage, &mut world);
+
+        let handle = world.get::<PhysicsHandle>(entity).unwrap().0;
+        let colliders = world.get_resource::<RigidBodySetRes>().unwrap()[handle]
+            .colliders()
+            .to_vec();
+        assert!(colliders.len() == 1);
+
+        world.get_mut::<Transform2>(entity).unwrap().pos = Vector2::new(10, 5);
+        run(&mut stage, &mut world);
+
+        let rigid_body_set = world.get_resource::<RigidBodySetRes>().unwrap();
+        let collider_set = world.get_resource::<ColliderSetRes>().unwrap();
+        assert!(*rigid_body_set[handle].translation() == vector![10.0, 5.0]);
+        assert!(rigid_body_set[handle].colliders() == colliders);
+        assert!(collider_set[colliders[0]].position().translation.vector == vector![10.0, 5.0]);
+        assert!(world.get::<Transform2>(entity).unwrap().pos == Vector2::new(10, 5));
+    }
+
+    #[test]
+    fn tile_map_rebuild() {
+        let mut world = physics_world();
+        let tile_map = spawn_tile_map(&mut world, &["##########"]);
+        let entity = body_on_floor(&mut world, 5.0, 1.5, KinematicBody::default());
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(0, -60));
+        run_frames(&mut stage, &mut world, 2);
+
+        let colliders = |world: &World| {
+            let handle = world.get::<PhysicsHandle>(tile_map).unwrap().0;
+            world.get_resource::<RigidBodySetRes>().unwrap()[handle]
+                .colliders()
+                .to_vec()
+        };
+        let built = colliders(&world);
+
+        // Marking the tile map as changed, as loading a frame does, keeps its colliders
+        world.get_mut::<TileMapCollider>(tile_map).unwrap().layer = u32::MAX;
+        run(&mut stage, &mut world);
+        assert!(colliders(&world) == built);
+
+        world
+            .get_mut::<TileMapCollider>(tile_map)
+            .unwrap()
+            .layer_mask = 1;
+        run(&mut stage, &mut world);
+        assert!(colliders(&world) == built);
+        let collider_set = world.get_resource::<ColliderSetRes>().unwrap();
+        assert!(collider_set[built[0]].collision_groups() == InteractionGroups::new(u32::MAX, 1));
+
+        // Bodies standing on the tile map still collide with it on the frame it is rebuilt
+        world
+            .get_mut::<TileMapCollider>(tile_map)
+            .unwrap()
+            .set(0, 0, TILE_EMPTY);
+        run(&mut stage, &mut world);
+        assert!(colliders(&world) != built);
+        assert!(near(pos(&world, entity).y, 1.5));
+        assert!(world.get::<KinematicBody>(entity).unwrap().is_on_floor);
+    }
+
+    #[test]
+    fn kinematic_floor() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &["##########"]);
+        let entity = spawn_kinematic(
+            &mut world,
+            Vector2::new(5, 4),
+            Vector2::splat(0.5.into()),
+            KinematicBody::default(),
+        );
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(0, -60));
+
+        run_frames(&mut stage, &mut world, 10);
+
+        let kinematic_body = world.get::<KinematicBody>(entity).unwrap();
+        assert!(near(pos(&world, entity).y, 1.5));
+        assert!(pos(&world, entity).x == Number::from(5));
+        assert!(kinematic_body.is_on_floor && !kinematic_body.is_on_wall);
+    }
+
+    #[test]
+    fn kinematic_wall() {
+        let mut world = physics_world();
+        spawn_tile_map(
+            &mut world,
+            &[
+                ".........#",
+                ".........#",
+                ".........#",
+                ".........#",
+                "##########",
+            ],
+        );
+        let entity = spawn_kinematic(
+            &mut world,
+            Vector2::new(5, 3),
+            Vector2::splat(0.5.into()),
+            KinematicBody::default(),
+        );
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(120, -60));
+
+        run_frames(&mut stage, &mut world, 10);
+
+        // Slides down the wall then along the floor into the corner
+        let kinematic_body = world.get::<KinematicBody>(entity).unwrap();
+        assert!(near(pos(&world, entity).x, 8.5));
+        assert!(near(pos(&world, entity).y, 1.5));
+        assert!(kinematic_body.is_on_wall && kinematic_body.is_on_floor);
+    }
+
+    #[test]
+    fn kinematic_penetration() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &[".....#", ".....#", ".....#"]);
+        // Starts overlapping the wall
+        let entity = spawn_kinematic(
+            &mut world,
+            Vector2::new(4.75, 1.5),
+            Vector2::splat(0.5.into()),
+            KinematicBody::default(),
+        );
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(60, 0));
+
+        run_frames(&mut stage, &mut world, 10);
+        assert!(pos(&world, entity).x <= Number::from(4.75));
+
+        set_velocity(&mut world, entity, Vector2::new(-60, 0));
+        run_frames(&mut stage, &mut world, 2);
+        assert!(pos(&world, entity).x < Number::from(4));
+    }
+
+    #[test]
+    fn kinematic_moving_platform() {
+        let mut world = physics_world();
+        let platform = spawn_kinematic(
+            &mut world,
+            Vector2::ZERO,
+            Vector2::new(2.0, 0.5),
+            KinematicBody::default(),
+        );
+        world.entity_mut(platform).insert(MovingPlatform {
+            points: vec![Vector2::new(10, 0), Vector2::ZERO],
+            speed: 60.into(),
+            ..Default::default()
+        });
+        let entity = spawn_kinematic(
+            &mut world,
+            Vector2::new(0.0, 1.0625),
+            Vector2::splat(0.5.into()),
+            KinematicBody::default(),
+        );
+        let mut stage = start(&mut world);
+
+        let platform_start = pos(&world, platform);
+        let body_start = pos(&world, entity);
+        run_frames(&mut stage, &mut world, 5);
+
+        let platform_moved = pos(&world, platform) - platform_start;
+        let body_moved = pos(&world, entity) - body_start;
+        assert!(near(platform_moved.x, 5.0));
+        assert!(near(body_moved.x, platform_moved.x.to_f32()));
+        assert!(near(body_moved.y, 0.0));
+        assert!(world.get::<KinematicBody>(entity).unwrap().is_on_floor);
+    }
+
+    #[test]
+    fn kinematic_contact() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &[".....#", "######"]);
+        // Starts touching the floor, which is part of the same tile map collider as the wall
+        let entity = spawn_kinematic(
+            &mut world,
+            Vector2::new(2.0, 1.5),
+            Vector2::splat(0.5.into()),
+            KinematicBody::default(),
+        );
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(60, 0));
+
+        run_frames(&mut stage, &mut world, 6);
+        assert!(near(pos(&world, entity).x, 4.5));
+        assert!(near(pos(&world, entity).y, 1.5));
+    }
+
+    #[test]
+    fn kinematic_slopes() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &["####################"]);
+        spawn_ramp(&mut world, 4.0, 6.0, 30.0);
+        spawn_ramp(&mut world, 14.0, 6.0, 60.0);
+        let gentle = body_on_floor(&mut world, 3.0, 1.5, KinematicBody::default());
+        let steep = body_on_floor(&mut world, 13.0, 1.5, KinematicBody::default());
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, gentle, Vector2::new(60, -30));
+        set_velocity(&mut world, steep, Vector2::new(60, -30));
+
+        run_frames(&mut stage, &mut world, 4);
+
+        // Walks up a slope under the max slope, along its surface
+        let pos_gentle = pos(&world, gentle);
+        let surface = (pos_gentle.x.to_f32() + 0.5 - 4.0) * 30f32.to_radians().tan();
+        assert!(pos_gentle.x > Number::from(5));
+        assert!(near(pos_gentle.y, 1.5 + surface));
+        assert!(world.get::<KinematicBody>(gentle).unwrap().is_on_floor);
+
+        // A slope over the max slope is a wall
+        let pos_steep = pos(&world, steep);
+        assert!(near(pos_steep.x, 13.5));
+        assert!(near(pos_steep.y, 1.5));
+        assert!(world.get::<KinematicBody>(steep).unwrap().is_on_wall);
+    }
+
+    #[test]
+    fn kinematic_step() {
+        // Steps onto a ledge of its step height, but not onto one twice as high
+        let ledges: [(&[&str], f32, f32); 2] = [
+            (&["....######", "##########"], 8.0, 2.5),
+            (&["....######", "....######", "##########"], 3.5, 1.5),
+        ];
+
+        for (rows, x, y) in ledges {
+            let mut world = physics_world();
+            spawn_tile_map(&mut world, rows);
+            let stepping = KinematicBody {
+                step_height: 1.into(),
+                ..Default::default()
+            };
+            let entity = body_on_floor(&mut world, 2.0, 1.5, stepping);
+            let mut stage = start(&mut world);
+            set_velocity(&mut world, entity, Vector2::new(60, -30));
+
+            run_frames(&mut stage, &mut world, 6);
+            assert!(near(pos(&world, entity).x, x));
+            assert!(near(pos(&world, entity).y, y));
+            assert!(world.get::<KinematicBody>(entity).unwrap().is_on_floor);
+        }
+    }
+
+    #[test]
+    fn kinematic_snap() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &["####################"]);
+        spawn_ramp(&mut world, 0.0, 10.0, -30.0);
+        let snapping = KinematicBody {
+            snap_distance: 1.into(),
+            ..Default::default()
+        };
+        let ramp_height = 10.0 * 30f32.to_radians().tan();
+        let entity = body_on_floor(&mut world, 1.0, 1.5 + ramp_height, snapping);
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(0, -60));
+        run_frames(&mut stage, &mut world, 4);
+        assert!(world.get::<KinematicBody>(entity).unwrap().is_on_floor);
+
+        // Snaps down the slope instead of leaving it when walking downhill
+        set_velocity(&mut world, entity, Vector2::new(60, 0));
+        let start_pos = pos(&world, entity);
+        for _ in 0..4 {
+            run(&mut stage, &mut world);
+            assert!(world.get::<KinematicBody>(entity).unwrap().is_on_floor);
+        }
+
+        let moved = pos(&world, entity) - start_pos;
+        assert!(near(moved.x, 4.0));
+        assert!(near(moved.y, -4.0 * 30f32.to_radians().tan()));
+    }
+
+    #[test]
+    fn kinematic_debug_rays() {
+        let mut world = physics_world();
+        world.insert_resource(PhysicsDebugRaysRes::default());
+        world.insert_resource(PhysicsDebugRenderRes::default());
+        spawn_tile_map(&mut world, &["##########"]);
+        let entity = body_on_floor(&mut world, 5.0, 4.0, KinematicBody::default());
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(0, -60));
+
+        run(&mut stage, &mut world);
+        assert!(world
+            .get_resource::<PhysicsDebugRaysRes>()
+            .unwrap()
+            .is_empty());
+
+        // Records the casts only while the debug render draws them
+        world
+            .get_resource_mut::<PhysicsDebugRenderRes>()
+            .unwrap()
+            .enabled = true;
+        run_frames(&mut stage, &mut world, 3);
+
+        let debug_rays = world.get_resource::<PhysicsDebugRaysRes>().unwrap();
+        assert!(debug_rays.iter().any(|debug_ray| debug_ray.toi.is_some()));
+        assert!(debug_rays
+            .iter()
+            .all(|debug_ray| debug_ray.ray.origin.x == 5.0 && debug_ray.max_toi == 1.0));
+    }
+
+    #[test]
+    fn kinematic_teleport() {
+        let mut world = physics_world();
+        spawn_tile_map(&mut world, &["..........#", "..........#", "###########"]);
+        let entity = body_on_floor(&mut world, 2.0, 1.5, KinematicBody::default());
+        let mut stage = start(&mut world);
+        set_velocity(&mut world, entity, Vector2::new(60, 0));
+
+        // Collides from where it was teleported to on the teleport frame
+        world
+            .entity_mut(entity)
+            .insert(Teleport::from_pos(Vector2::new(9.25, 1.5)));
+        run(&mut stage, &mut world);
+        assert!(near(pos(&world, entity).x, 9.5));
+    }
+}
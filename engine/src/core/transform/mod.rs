@@ -1,5 +1,7 @@
+mod smoothing;
 mod structs;
 mod systems;
 
+pub use smoothing::*;
 pub use structs::*;
 pub use systems::*;
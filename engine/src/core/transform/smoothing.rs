@@ -0,0 +1,433 @@
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use derive_more::{Deref, DerefMut};
+use ggrs::P2PSession;
+use std::collections::VecDeque;
+
+use crate::core::maths::OverflowContext;
+use crate::core::transform::{rendered_transform, GlobalTransform2};
+use crate::core::EngineGGRSConfig;
+
+pub struct TransformSmoothingPlugin;
+
+impl Plugin for TransformSmoothingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TransformSmoothingRes>()
+            .init_resource::<RollbackFrameRes>()
+            .init_resource::<SimulationStepsRes>()
+            .init_resource::<ConfirmedFrameRes>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                transform_smoothing_confirmed_frame_system
+                    .label(TransformSmoothingSystem::ConfirmedFrame),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                transform_smoothing_system
+                    .label(TransformSmoothingSystem::Smoothing)
+                    .after(TransformSmoothingSystem::ConfirmedFrame)
+                    .before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, SystemLabel)]
+pub enum TransformSmoothingSystem {
+    ConfirmedFrame,
+    Smoothing,
+}
+
+// Transform smoothing resources
+
+pub struct TransformSmoothingRes {
+    pub tick_duration: f32,
+    since_tick: f32,
+    frame: u32,
+    steps: u64,
+}
+
+impl TransformSmoothingRes {
+    pub fn from_update_frequency(update_frequency: usize) -> Self {
+        Self {
+            tick_duration: 1.0 / update_frequency as f32,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for TransformSmoothingRes {
+    fn default() -> Self {
+        Self {
+            tick_duration: 1.0 / 60.0,
+            since_tick: 0.0,
+            frame: 0,
+            steps: 0,
+        }
+    }
+}
+
+// Frame of the simulation state, restored on rollback
+#[derive(Copy, Clone, Default, Hash, Deref, DerefMut, Reflect, Component)]
+#[reflect(Hash)]
+pub struct RollbackFrameRes(pub u32);
+
+// Simulated steps including resimulated ones, never restored on rollback
+#[derive(Default, Deref, DerefMut)]
+pub struct SimulationStepsRes(pub u64);
+
+// Latest RollbackFrameRes whose inputs are all confirmed by ggrs, so that its state can no longer
+// be corrected by a rollback
+#[derive(Default, Deref, DerefMut)]
+pub struct ConfirmedFrameRes(pub u32);
+
+// Transform smoothing ECS components
+
+// Entities with this component are rendered outside the rollback schedule, interpolated between
+// the two last simulated frames and with rollback corrections blended out over correction_frames.
+// With confirmed_only, only frames confirmed by ggrs are rendered, which never need corrections
+// but lag behind by the prediction.
+#[derive(Clone, Component)]
+pub struct TransformSmoothing {
+    pub interpolate: bool,
+    pub correction_frames: u32,
+    pub confirmed_only: bool,
+}
+
+impl Default for TransformSmoothing {
+    fn default() -> Self {
+        Self {
+            interpolate: true,
+            correction_frames: 6,
+            confirmed_only: false,
+        }
+    }
+}
+
+// Frames kept when ggrs confirms none, more than any prediction window
+const HISTORY_CAPACITY: usize = 64;
+
+#[derive(Clone, Component)]
+pub struct TransformSmoothingState {
+    // Rendered transform of each simulated frame, rewritten when a rollback simulates it again
+    history: VecDeque<(u32, Transform)>,
+    translation_error: Vec3,
+    rotation_error: Quat,
+}
+
+impl TransformSmoothingState {
+    fn new(frame: u32, transform: Transform) -> Self {
+        Self {
+            history: VecDeque::from([(frame, transform)]),
+            translation_error: Vec3::ZERO,
+            rotation_error: Quat::IDENTITY,
+        }
+    }
+
+    fn record(&mut self, frame: u32, transform: Transform, confirmed_frame: u32) {
+        // Frames from the rolled back one onward are simulated again
+        while matches!(self.history.back(), Some((recorded, _)) if !is_before(*recorded, frame)) {
+            self.history.pop_back();
+        }
+        self.history.push_back((frame, transform));
+
+        // Confirmed frames never change, only the confirmed frame and the one before are needed
+        let oldest_needed = confirmed_frame.wrapping_sub(1);
+        while self.history.len() > HISTORY_CAPACITY
+            || matches!(self.history.get(1), Some((second, _)) if !is_before(oldest_needed, *second))
+        {
+            self.history.pop_front();
+        }
+    }
+
+    // Latest recorded transform up to the frame
+    fn sample(&self, frame: u32) -> Transform {
+        self.history
+            .iter()
+            .rev()
+            .find(|(recorded, _)| !is_before(frame, *recorded))
+            .or_else(|| self.history.front())
+            .map(|(_, transform)| *transform)
+            .unwrap_or_default()
+    }
+
+    fn interpolated(&self, smoothing: &TransformSmoothing, frame: u32, alpha: f32) -> Transform {
+        let current = self.sample(frame);
+        if !smoothing.interpolate {
+            return current;
+        }
+
+        let previous = self.sample(frame.wrapping_sub(1));
+        Transform {
+            translation: previous.translation.lerp(current.translation, alpha),
+            rotation: previous.rotation.slerp(current.rotation, alpha),
+            scale: previous.scale.lerp(current.scale, alpha),
+        }
+    }
+}
+
+// Frames wrap around, a frame is before another if it is less than half the range behind
+fn is_before(frame: u32, other: u32) -> bool {
+    let behind = other.wrapping_sub(frame);
+    behind != 0 && behind < u32::MAX / 2
+}
+
+// Transform smoothing systems
+
+pub fn transform_smoothing_frame_system(
+    mut rollback_frame: ResMut<RollbackFrameRes>,
+    mut simulation_steps: ResMut<SimulationStepsRes>,
+) {
+    **rollback_frame = rollback_frame.wrapping_add(1);
+    **simulation_steps += 1;
+}
+
+type TransformSmoothingRecordQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform2,
+        Option<&'static Parent>,
+        Option<&'static mut TransformSmoothingState>,
+    ),
+    With<TransformSmoothing>,
+>;
+
+// Runs in the rollback schedule after the frame system, so that resimulated frames overwrite the
+// mispredicted transforms they replace
+pub fn transform_smoothing_record_system(
+    mut commands: Commands,
+    rollback_frame: Res<RollbackFrameRes>,
+    confirmed_frame: Res<ConfirmedFrameRes>,
+    //
+    parent_query: Query<&GlobalTransform2>,
+    mut query: TransformSmoothingRecordQuery,
+) {
+    for (entity, global_transform2, parent, state) in query.iter_mut() {
+        let _overflow_context =
+            OverflowContext::enter("transform_smoothing_record_system", Some(entity));
+
+        let transform = rendered_transform(
+            global_transform2,
+            parent.and_then(|parent| parent_query.get(parent.0).ok()),
+        );
+
+        match state {
+            Some(mut state) => state.record(**rollback_frame, transform, **confirmed_frame),
+            None => {
+                commands
+                    .entity(entity)
+                    .insert(TransformSmoothingState::new(**rollback_frame, transform));
+            }
+        }
+    }
+}
+
+// Every frame of the sessions without prediction is confirmed as soon as it is simulated
+pub fn transform_smoothing_confirmed_frame_system(
+    rollback_frame: Res<RollbackFrameRes>,
+    mut confirmed_frame: ResMut<ConfirmedFrameRes>,
+    p2p_session: Option<Res<P2PSession<EngineGGRSConfig>>>,
+) {
+    **confirmed_frame = match p2p_session {
+        // The state after simulating ggrs frame N is recorded as RollbackFrameRes N + 1
+        Some(session) => (session.confirmed_frame() + 1).max(0) as u32,
+        None => **rollback_frame,
+    };
+}
+
+type TransformSmoothingQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static TransformSmoothing,
+        &'static GlobalTransform2,
+        Option<&'static Parent>,
+        &'static mut Transform,
+        Option<&'static mut TransformSmoothingState>,
+    ),
+>;
+
+#[allow(clippy::too_many_arguments)]
+pub fn transform_smoothing_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    rollback_frame: Res<RollbackFrameRes>,
+    confirmed_frame: Res<ConfirmedFrameRes>,
+    simulation_steps: Res<SimulationStepsRes>,
+    mut smoothing_res: ResMut<TransformSmoothingRes>,
+    //
+    parent_query: Query<&GlobalTransform2>,
+    mut query: TransformSmoothingQuery,
+) {
+    let frames = rollback_frame.wrapping_sub(smoothing_res.frame);
+    let steps = **simulation_steps - smoothing_res.steps;
+    // More steps than frames means some frames were simulated again after a rollback
+    let rolled_back = steps > frames as u64;
+
+    if frames > 0 {
+        smoothing_res.since_tick = 0.0;
+    } else {
+        smoothing_res.since_tick += time.delta_seconds();
+    }
+    smoothing_res.frame = **rollback_frame;
+    smoothing_res.steps = **simulation_steps;
+
+    let alpha = (smoothing_res.since_tick / smoothing_res.tick_duration).min(1.0);
+
+    for (entity, smoothing, global_transform2, parent, mut transform, state) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("transform_smoothing_system", Some(entity));
+
+        // Not simulated yet, rendered as is until the rollback schedule records it
+        let mut state = match state {
+            Some(state) => state,
+            None => {
+                let target = rendered_transform(
+                    global_transform2,
+                    parent.and_then(|parent| parent_query.get(parent.0).ok()),
+                );
+
+                commands
+                    .entity(entity)
+                    .insert(TransformSmoothingState::new(**rollback_frame, target));
+                *transform = target;
+                continue;
+            }
+        };
+
+        let frame = if smoothing.confirmed_only && is_before(**confirmed_frame, **rollback_frame) {
+            **confirmed_frame
+        } else {
+            **rollback_frame
+        };
+        let interpolated = state.interpolated(smoothing, frame, alpha);
+
+        // The jump between what was displayed and the corrected state becomes an error offset
+        // that fades out instead of a visible teleport, confirmed frames are never corrected
+        if frames > 0 && rolled_back && !smoothing.confirmed_only && smoothing.correction_frames > 0
+        {
+            state.translation_error = transform.translation - interpolated.translation;
+            state.rotation_error = transform.rotation * interpolated.rotation.inverse();
+        }
+
+        if smoothing.correction_frames > 0 {
+            let decay = (time.delta_seconds()
+                / (smoothing.correction_frames as f32 * smoothing_res.tick_duration))
+                .min(1.0);
+
+            state.translation_error *= 1.0 - decay;
+            state.rotation_error = state.rotation_error.slerp(Quat::IDENTITY, decay);
+        } else {
+            state.translation_error = Vec3::ZERO;
+            state.rotation_error = Quat::IDENTITY;
+        }
+
+        *transform = Transform {
+            translation: interpolated.translation + state.translation_error,
+            rotation: state.rotation_error * interpolated.rotation,
+            scale: interpolated.scale,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::core::maths::Vector2;
+    use crate::core::transform::*;
+
+    fn smoothing_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        world.insert_resource(RollbackFrameRes::default());
+        world.insert_resource(SimulationStepsRes::default());
+        world.insert_resource(ConfirmedFrameRes::default());
+        world.insert_resource(TransformSmoothingRes::default());
+        world
+    }
+
+    fn spawn(world: &mut World, smoothing: TransformSmoothing) -> Entity {
+        world
+            .spawn()
+            .insert_bundle(Transform2Bundle::default())
+            .insert(Transform::default())
+            .insert(smoothing)
+            .id()
+    }
+
+    // Simulates one frame of the rollback schedule with every entity at x
+    fn simulate(world: &mut World, x: i32) {
+        for mut transform2 in world.query::<&mut Transform2>().iter_mut(world) {
+            transform2.pos = Vector2::new(x, 0);
+        }
+        transform_synchronization_stage().run(world);
+    }
+
+    fn render(world: &mut World, entity: Entity) -> f32 {
+        SystemStage::single_threaded()
+            .with_system(transform_smoothing_system)
+            .run(world);
+        world.get::<Transform>(entity).unwrap().translation.x
+    }
+
+    #[test]
+    fn corrections() {
+        let mut world = smoothing_world();
+        let smoothed = spawn(&mut world, TransformSmoothing::default());
+        let snapped = spawn(
+            &mut world,
+            TransformSmoothing {
+                correction_frames: 0,
+                ..Default::default()
+            },
+        );
+
+        for x in 1..=3 {
+            simulate(&mut world, x);
+        }
+        // Time does not advance, so frames are rendered at the start of their interpolation
+        assert!(render(&mut world, smoothed) == 2.0);
+        assert!(render(&mut world, snapped) == 2.0);
+
+        // Frames 2 and 3 are corrected by a rollback, then frame 4 is simulated
+        **world.get_resource_mut::<RollbackFrameRes>().unwrap() = 1;
+        for x in [200, 300, 400] {
+            simulate(&mut world, x);
+        }
+
+        // The corrected frame 3 is interpolated rather than its mispredicted state
+        assert!(render(&mut world, smoothed) == 2.0);
+        let state = world.get::<TransformSmoothingState>(smoothed).unwrap();
+        assert!(state.translation_error.x == 2.0 - 300.0);
+        assert!(render(&mut world, snapped) == 300.0);
+    }
+
+    #[test]
+    fn confirmed_only() {
+        let mut world = smoothing_world();
+        let entity = spawn(
+            &mut world,
+            TransformSmoothing {
+                confirmed_only: true,
+                ..Default::default()
+            },
+        );
+
+        for x in 1..=4 {
+            simulate(&mut world, x);
+        }
+        **world.get_resource_mut::<ConfirmedFrameRes>().unwrap() = 2;
+        assert!(render(&mut world, entity) == 1.0);
+
+        **world.get_resource_mut::<ConfirmedFrameRes>().unwrap() = 4;
+        assert!(render(&mut world, entity) == 3.0);
+
+        // Frames before the one preceding the confirmed frame are dropped
+        simulate(&mut world, 5);
+        let state = world.get::<TransformSmoothingState>(entity).unwrap();
+        assert!(state.history.iter().map(|(frame, _)| *frame).eq([3, 4, 5]));
+        assert!(state.sample(1).translation.x == 3.0);
+    }
+}
@@ -1,16 +1,36 @@
-use bevy::prelude::Component;
+use bevy::prelude::{Bundle, Component};
 use bevy::reflect::Reflect;
+use derive_more::{Deref, DerefMut};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Mul;
 
-use crate::core::maths::{Number, Vector2};
+use crate::core::maths::{Affine2, Angle, Number, Vector2};
 
-#[derive(Default, Reflect, Component)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Reflect, Component, Serialize, Deserialize)]
+#[reflect(Hash, PartialEq)]
 pub struct Transform2 {
     pub pos: Vector2,
     pub scale: Vector2,
-    pub rotation: Number,
+    pub rotation: Angle,
+    // Draw order, mapped onto the z translation of the rendered Transform
+    pub z_order: i32,
+}
+
+impl Default for Transform2 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
 }
 
 impl Transform2 {
+    pub const IDENTITY: Transform2 = Transform2 {
+        pos: Vector2::ZERO,
+        scale: Vector2::ONE,
+        rotation: Angle::ZERO,
+        z_order: 0,
+    };
+
     pub fn from_pos(pos: Vector2) -> Self {
         Self {
             pos,
@@ -23,10 +43,198 @@ impl Transform2 {
             ..Default::default()
         }
     }
-    pub fn from_rotation(rotation: Number) -> Self {
+    pub fn from_rotation(rotation: Angle) -> Self {
         Self {
             rotation,
             ..Default::default()
         }
     }
+    pub fn from_z_order(z_order: i32) -> Self {
+        Self {
+            z_order,
+            ..Default::default()
+        }
+    }
+
+    pub fn affine(&self) -> Affine2 {
+        Affine2::from_scale_rotation_translation(self.scale, self.rotation, self.pos)
+    }
+
+    pub fn transform_vector(&self, vector: Vector2) -> Vector2 {
+        Vector2 {
+            x: vector.x * self.scale.x,
+            y: vector.y * self.scale.y,
+        }
+        .rotate(self.rotation)
+    }
+
+    pub fn transform_point(&self, point: Vector2) -> Vector2 {
+        self.transform_vector(point) + self.pos
+    }
+
+    // Returns None when the scale collapses an axis
+    pub fn inverse_transform_vector(&self, vector: Vector2) -> Option<Vector2> {
+        if self.scale.x == Number::ZERO || self.scale.y == Number::ZERO {
+            return None;
+        }
+
+        let vector = vector.rotate(-self.rotation);
+        Some(Vector2 {
+            x: vector.x / self.scale.x,
+            y: vector.y / self.scale.y,
+        })
+    }
+
+    pub fn inverse_transform_point(&self, point: Vector2) -> Option<Vector2> {
+        self.inverse_transform_vector(point - self.pos)
+    }
+
+    // Applies child in the space of self. A sheared result cannot be stored in a Transform2, so
+    // this is exact only when the scale of self is uniform, use affine() otherwise.
+    pub fn mul_transform(&self, child: &Transform2) -> Transform2 {
+        Transform2 {
+            pos: self.transform_point(child.pos),
+            scale: Vector2 {
+                x: self.scale.x * child.scale.x,
+                y: self.scale.y * child.scale.y,
+            },
+            rotation: self.rotation + child.rotation,
+            z_order: self.z_order.saturating_add(child.z_order),
+        }
+    }
+
+    // Returns None when the scale collapses an axis, exact only when the scale is uniform
+    pub fn inverse(&self) -> Option<Transform2> {
+        let pos = self.inverse_transform_point(Vector2::ZERO)?;
+
+        Some(Transform2 {
+            pos,
+            scale: Vector2 {
+                x: Number::ONE / self.scale.x,
+                y: Number::ONE / self.scale.y,
+            },
+            rotation: -self.rotation,
+            z_order: self.z_order.saturating_neg(),
+        })
+    }
+
+    // Interpolates rotations along the shortest arc
+    pub fn lerp(&self, other: &Transform2, t: Number) -> Transform2 {
+        Transform2 {
+            pos: self.pos.lerp(other.pos, t),
+            scale: self.scale.lerp(other.scale, t),
+            rotation: self.rotation.lerp(other.rotation, t),
+            z_order: t.lerp_i32(self.z_order, other.z_order),
+        }
+    }
+}
+
+impl Mul for Transform2 {
+    type Output = Transform2;
+
+    fn mul(self, child: Transform2) -> Transform2 {
+        self.mul_transform(&child)
+    }
+}
+
+// World space transform, computed from Transform2 and the Parent hierarchy every frame
+
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Hash,
+    Reflect,
+    Component,
+    Deref,
+    DerefMut,
+    Serialize,
+    Deserialize,
+)]
+#[reflect(Hash, PartialEq)]
+pub struct GlobalTransform2(pub Transform2);
+
+// Spawns with a GlobalTransform2 so that the entity is not rendered at the origin until the next
+// propagation. Exact for roots, children are corrected by the propagation of the same frame.
+#[derive(Default, Bundle)]
+pub struct Transform2Bundle {
+    pub transform2: Transform2,
+    pub global_transform2: GlobalTransform2,
+}
+
+impl From<Transform2> for Transform2Bundle {
+    fn from(transform2: Transform2) -> Self {
+        Self {
+            transform2,
+            global_transform2: GlobalTransform2(transform2),
+        }
+    }
+}
+
+impl fmt::Display for Transform2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pos: {}, scale: {}, rotation: {}, z_order: {}",
+            self.pos, self.scale, self.rotation, self.z_order
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::core::maths::{Angle, Number, Vector2};
+    use crate::core::transform::Transform2;
+
+    fn transform() -> Transform2 {
+        Transform2 {
+            pos: Vector2::new(10, 0),
+            scale: Vector2::new(2, 2),
+            rotation: Angle::from_radians(Number::FRAC_PI_2),
+            z_order: 1,
+        }
+    }
+
+    #[test]
+    fn points() {
+        let transform = transform();
+
+        assert!(transform.transform_point(Vector2::RIGHT) == Vector2::new(10, 2));
+        assert!(transform.transform_vector(Vector2::UP) == Vector2::new(-2, 0));
+        assert!(transform.inverse_transform_point(Vector2::new(10, 2)) == Some(Vector2::RIGHT));
+        assert!(transform.affine().transform_point(Vector2::ONE) == Vector2::new(8, 2));
+        assert!(Transform2::from_scale(Vector2::ZERO).inverse().is_none());
+        assert!(Transform2::default().transform_point(Vector2::ONE) == Vector2::ONE);
+    }
+
+    #[test]
+    fn composition() {
+        let parent = transform();
+        let child = Transform2::from_pos(Vector2::new(1, 0));
+        let global = parent * child;
+
+        assert!(global.pos == Vector2::new(10, 2));
+        assert!(global.z_order == 1);
+        assert!(
+            global.transform_point(Vector2::ONE)
+                == parent.affine().transform_point(Vector2::new(2, 1))
+        );
+        assert!((global * global.inverse().unwrap()).pos == Vector2::ZERO);
+        assert!(parent.lerp(&child, Number::ONE) == child);
+    }
+
+    #[test]
+    fn extreme_z_orders() {
+        let top = Transform2::from_z_order(i32::MAX);
+        let bottom = Transform2::from_z_order(i32::MIN);
+
+        assert!((top * top).z_order == i32::MAX);
+        assert!((bottom * bottom).z_order == i32::MIN);
+        assert!(bottom.inverse().unwrap().z_order == i32::MAX);
+        assert!(bottom.lerp(&top, Number::from(0.5)).z_order == 0);
+        assert!(bottom.lerp(&top, Number::ONE).z_order == i32::MAX);
+    }
 }
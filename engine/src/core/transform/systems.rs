@@ -1,12 +1,160 @@
-use bevy::math::Quat;
-use bevy::prelude::{Changed, Query, Transform};
+use bevy::math::{Quat, Vec2};
+use bevy::prelude::{
+    Children, Commands, Entity, ParallelSystemDescriptorCoercion, Parent, Query, SystemLabel,
+    SystemStage, Transform, Without,
+};
 
-use crate::core::transform::Transform2;
+use crate::core::maths::OverflowContext;
+use crate::core::transform::{
+    transform_smoothing_frame_system, transform_smoothing_record_system, GlobalTransform2,
+    Transform2, TransformSmoothing,
+};
 
-pub fn sync_transform_system(mut query: Query<(&mut Transform, &Transform2), Changed<Transform2>>) {
-    for (mut transform, transform2) in query.iter_mut() {
-        transform.rotation = Quat::from_rotation_z(transform2.rotation.into());
-        transform.translation.x = transform2.pos.x.into();
-        transform.translation.y = transform2.pos.y.into();
+pub fn propagate_transform_system(
+    mut commands: Commands,
+    query: Query<(Entity, &Transform2, Option<&Parent>, Option<&Children>)>,
+    mut global_query: Query<&mut GlobalTransform2>,
+) {
+    // Each global transform only depends on its ancestors, so the traversal order is irrelevant
+    let mut stack = query
+        .iter()
+        .filter(|(_, _, parent, _)| parent.is_none_or(|parent| query.get(parent.0).is_err()))
+        .map(|(entity, transform2, _, _)| (entity, *transform2))
+        .collect::<Vec<_>>();
+
+    while let Some((entity, global)) = stack.pop() {
+        let _overflow_context = OverflowContext::enter("propagate_transform_system", Some(entity));
+
+        match global_query.get_mut(entity) {
+            Ok(mut global_transform2) => {
+                if global_transform2.0 != global {
+                    global_transform2.0 = global;
+                }
+            }
+            // Entities spawned without a Transform2Bundle
+            Err(_) => {
+                commands.entity(entity).insert(GlobalTransform2(global));
+            }
+        }
+
+        if let Ok((_, _, _, Some(children))) = query.get(entity) {
+            for child in children.iter().rev() {
+                if let Ok((_, transform2, _, _)) = query.get(*child) {
+                    stack.push((*child, global.mul_transform(transform2)));
+                }
+            }
+        }
+    }
+}
+
+pub fn sync_transform_system(
+    mut query: Query<
+        (Entity, &mut Transform, &GlobalTransform2, Option<&Parent>),
+        Without<TransformSmoothing>,
+    >,
+    parent_query: Query<&GlobalTransform2>,
+) {
+    for (entity, mut transform, global_transform2, parent) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("sync_transform_system", Some(entity));
+
+        *transform = rendered_transform(
+            global_transform2,
+            parent.and_then(|parent| parent_query.get(parent.0).ok()),
+        );
+    }
+}
+
+// Transform synchronization stage, rendered transforms are synchronized after the propagation
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, SystemLabel)]
+pub enum TransformSynchronizationSystem {
+    Propagate,
+    Sync,
+    SmoothingFrame,
+    SmoothingRecord,
+}
+
+pub fn transform_synchronization_stage() -> SystemStage {
+    SystemStage::single_threaded()
+        .with_system(propagate_transform_system.label(TransformSynchronizationSystem::Propagate))
+        .with_system(
+            sync_transform_system
+                .label(TransformSynchronizationSystem::Sync)
+                .after(TransformSynchronizationSystem::Propagate),
+        )
+        .with_system(
+            transform_smoothing_frame_system
+                .label(TransformSynchronizationSystem::SmoothingFrame)
+                .after(TransformSynchronizationSystem::Sync),
+        )
+        .with_system(
+            transform_smoothing_record_system
+                .label(TransformSynchronizationSystem::SmoothingRecord)
+                .after(TransformSynchronizationSystem::SmoothingFrame),
+        )
+}
+
+// Bevy propagates its own Transform down the hierarchy, so children are rendered relative to the
+// world transform of their parent
+pub(crate) fn rendered_transform(
+    global_transform2: &GlobalTransform2,
+    parent_global_transform2: Option<&GlobalTransform2>,
+) -> Transform {
+    let rendered = world_transform(global_transform2);
+
+    match parent_global_transform2.map(|parent| world_transform(parent).compute_matrix()) {
+        Some(parent_matrix) if parent_matrix.determinant() != 0.0 => {
+            Transform::from_matrix(parent_matrix.inverse() * rendered.compute_matrix())
+        }
+        _ => rendered,
+    }
+}
+
+fn world_transform(global_transform2: &GlobalTransform2) -> Transform {
+    Transform {
+        translation: Vec2::from(global_transform2.pos).extend(global_transform2.z_order as f32),
+        rotation: Quat::from_rotation_z(global_transform2.rotation.radians().to_f32()),
+        scale: Vec2::from(global_transform2.scale).extend(1.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::core::maths::Vector2;
+    use crate::core::transform::*;
+
+    #[test]
+    fn synchronization() {
+        let mut world = World::new();
+        world.insert_resource(RollbackFrameRes::default());
+        world.insert_resource(SimulationStepsRes::default());
+        world.insert_resource(ConfirmedFrameRes::default());
+
+        let parent = world
+            .spawn()
+            .insert_bundle(Transform2Bundle::from(Transform2::from_pos(Vector2::new(
+                5, 0,
+            ))))
+            .insert(Transform::default())
+            .id();
+        let child = world
+            .spawn()
+            .insert_bundle(Transform2Bundle::from(Transform2::from_pos(Vector2::new(
+                0, 2,
+            ))))
+            .insert(Transform::default())
+            .id();
+        world.entity_mut(parent).push_children(&[child]);
+
+        // Spawned roots already have their world transform
+        assert!(world.get::<GlobalTransform2>(parent).unwrap().pos == Vector2::new(5, 0));
+
+        transform_synchronization_stage().run(&mut world);
+
+        assert!(world.get::<GlobalTransform2>(child).unwrap().pos == Vector2::new(5, 2));
+        assert!(world.get::<Transform>(parent).unwrap().translation == Vec3::new(5.0, 0.0, 0.0));
+        assert!(world.get::<Transform>(child).unwrap().translation == Vec3::new(0.0, 2.0, 0.0));
     }
 }
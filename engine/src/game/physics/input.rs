@@ -1,33 +1,541 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 use bytemuck::{Pod, Zeroable};
 use ggrs::PlayerHandle;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
 
-pub const INPUT_UP: u8 = 1 << 0;
-pub const INPUT_DOWN: u8 = 1 << 1;
-pub const INPUT_LEFT: u8 = 1 << 2;
-pub const INPUT_RIGHT: u8 = 1 << 3;
+use crate::config::ConfigError;
+use crate::core::maths::{Number, Vector2};
 
+pub const INPUT_MAP_PATH: &str = "input_map.ron";
+
+const STICK_RESOLUTION: f32 = i8::MAX as f32;
+const TRIGGER_RESOLUTION: f32 = u8::MAX as f32;
+
+// Analog values are quantized before entering the simulation so that every peer sees the same
+// bits, whatever the float behavior of their gamepad driver
 #[repr(C)]
-#[derive(Pod, Copy, Clone, PartialEq, Zeroable)]
+#[derive(
+    Pod, Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Zeroable, Serialize, Deserialize,
+)]
 pub struct GameInput {
     pub mask: u8,
+    pub stick_x: i8,
+    pub stick_y: i8,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+}
+
+impl GameInput {
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.mask & action.mask() != 0
+    }
+
+    // Returns the stick position with each axis in [-1, 1]
+    pub fn stick(&self) -> Vector2 {
+        Vector2::new(self.stick_x, self.stick_y) / Number::from(i8::MAX)
+    }
+
+    // Returns the left trigger pressure in [0, 1]
+    pub fn left_trigger(&self) -> Number {
+        Number::from(self.left_trigger) / Number::from(u8::MAX)
+    }
+
+    // Returns the right trigger pressure in [0, 1]
+    pub fn right_trigger(&self) -> Number {
+        Number::from(self.right_trigger) / Number::from(u8::MAX)
+    }
+}
+
+// Input actions, each one is packed as a bit of GameInput.mask
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum InputAction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 4] = [
+        InputAction::Up,
+        InputAction::Down,
+        InputAction::Left,
+        InputAction::Right,
+    ];
+
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    MouseButton(MouseButton),
+    GamepadButton(GamepadButtonType),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputBindings(pub BTreeMap<InputAction, Vec<InputBinding>>);
+
+impl InputBindings {
+    pub fn bind(&mut self, action: InputAction, binding: InputBinding) {
+        let bindings = self.0.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: InputAction, binding: InputBinding) {
+        if let Some(bindings) = self.0.get_mut(&action) {
+            bindings.retain(|bound| *bound != binding);
+        }
+    }
+
+    // Replaces every binding of the action
+    pub fn rebind(&mut self, action: InputAction, binding: InputBinding) {
+        self.0.insert(action, vec![binding]);
+    }
+
+    pub fn pressed(
+        &self,
+        action: InputAction,
+        binding_pressed: impl Fn(&InputBinding) -> bool,
+    ) -> bool {
+        self.0
+            .get(&action)
+            .is_some_and(|bindings| bindings.iter().any(binding_pressed))
+    }
+
+    pub fn mask(&self, binding_pressed: impl Fn(&InputBinding) -> bool) -> u8 {
+        InputAction::ALL
+            .iter()
+            .filter(|action| self.pressed(**action, &binding_pressed))
+            .fold(0, |mask, action| mask | action.mask())
+    }
+
+    fn from_keys(keys: [KeyCode; 4]) -> Self {
+        let mut bindings = Self::default();
+        for (action, key) in InputAction::ALL.into_iter().zip(keys) {
+            bindings.bind(action, InputBinding::Key(key));
+        }
+        bindings
+    }
+}
+
+// Input devices a local player can be assigned to
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum InputDevice {
+    KeyboardA,
+    KeyboardB,
+    Gamepad(usize),
+}
+
+impl InputDevice {
+    // Device of the nth local player when none is given: keyboard-a, keyboard-b, then gamepad-0
+    // onward
+    pub fn default_for(index: usize) -> Self {
+        match index {
+            0 => InputDevice::KeyboardA,
+            1 => InputDevice::KeyboardB,
+            _ => InputDevice::Gamepad(index - 2),
+        }
+    }
+}
+
+impl FromStr for InputDevice {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "keyboard-a" => Ok(InputDevice::KeyboardA),
+            "keyboard-b" => Ok(InputDevice::KeyboardB),
+            _ => value
+                .strip_prefix("gamepad-")
+                .and_then(|id| id.parse().ok())
+                .map(InputDevice::Gamepad)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid input device {}, expected keyboard-a, keyboard-b or gamepad-N",
+                        value
+                    )
+                }),
+        }
+    }
+}
+
+// Input map resource, validated whenever it is deserialized
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(remote = "Self", default, deny_unknown_fields)]
+pub struct InputMapRes {
+    pub keyboard_a: InputBindings,
+    pub keyboard_b: InputBindings,
+    pub gamepad: InputBindings,
+    pub stick_deadzone: f32,
+    pub trigger_deadzone: f32,
+    // Devices of the local players in handle order, overridden by the command line
+    pub local_devices: Vec<InputDevice>,
+}
+
+impl Default for InputMapRes {
+    fn default() -> Self {
+        let mut gamepad = InputBindings::default();
+        for (action, button_type) in InputAction::ALL.into_iter().zip([
+            GamepadButtonType::DPadUp,
+            GamepadButtonType::DPadDown,
+            GamepadButtonType::DPadLeft,
+            GamepadButtonType::DPadRight,
+        ]) {
+            gamepad.bind(action, InputBinding::GamepadButton(button_type));
+        }
+
+        Self {
+            keyboard_a: InputBindings::from_keys([
+                KeyCode::Up,
+                KeyCode::Down,
+                KeyCode::Left,
+                KeyCode::Right,
+            ]),
+            keyboard_b: InputBindings::from_keys([KeyCode::W, KeyCode::S, KeyCode::A, KeyCode::D]),
+            gamepad,
+            stick_deadzone: 0.2,
+            trigger_deadzone: 0.1,
+            local_devices: Vec::new(),
+        }
+    }
+}
+
+impl Serialize for InputMapRes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        InputMapRes::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for InputMapRes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let input_map = InputMapRes::deserialize(deserializer)?;
+        input_map.validate().map_err(de::Error::custom)?;
+        Ok(input_map)
+    }
+}
+
+impl InputMapRes {
+    // Deadzones of 1 or more would divide by zero when rescaling
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if !(0.0..1.0).contains(&self.stick_deadzone) {
+            return Err(ConfigError::new("stick_deadzone", "must be in [0, 1)"));
+        }
+        if !(0.0..1.0).contains(&self.trigger_deadzone) {
+            return Err(ConfigError::new("trigger_deadzone", "must be in [0, 1)"));
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let input_map = fs::read_to_string(path)?;
+
+        ron::from_str(&input_map)
+            .map_err(|error| format!("Invalid input map {}: {}", path.display(), error).into())
+    }
+
+    // Only a missing file falls back to the default bindings, an invalid one is an error
+    pub fn load_or_default(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let pretty = ron::ser::PrettyConfig::default();
+        fs::write(path, ron::ser::to_string_pretty(self, pretty)?)?;
+        Ok(())
+    }
+
+    // Binds keys and mouse buttons on keyboard layout A and gamepad buttons on gamepads
+    pub fn bind(&mut self, action: InputAction, binding: InputBinding) {
+        self.device_bindings(binding).bind(action, binding);
+    }
+
+    pub fn unbind(&mut self, action: InputAction, binding: InputBinding) {
+        self.device_bindings(binding).unbind(action, binding);
+    }
+
+    // Replaces every binding of the action on the device of the binding
+    pub fn rebind(&mut self, action: InputAction, binding: InputBinding) {
+        self.device_bindings(binding).rebind(action, binding);
+    }
+
+    fn device_bindings(&mut self, binding: InputBinding) -> &mut InputBindings {
+        match binding {
+            InputBinding::Key(_) | InputBinding::MouseButton(_) => &mut self.keyboard_a,
+            InputBinding::GamepadButton(_) => &mut self.gamepad,
+        }
+    }
+
+    // Applies a radial deadzone and rescales the remaining range so that the stick still reaches
+    // its full deflection
+    pub fn quantize_stick(&self, x: f32, y: f32) -> (i8, i8) {
+        let length = x.hypot(y);
+
+        if length <= self.stick_deadzone || length == 0.0 {
+            return (0, 0);
+        }
+
+        let scale =
+            ((length - self.stick_deadzone) / (1.0 - self.stick_deadzone)).min(1.0) / length;
+        let quantize = |value: f32| (value * scale * STICK_RESOLUTION).round() as i8;
+
+        (quantize(x), quantize(y))
+    }
+
+    pub fn quantize_trigger(&self, value: f32) -> u8 {
+        if value <= self.trigger_deadzone {
+            return 0;
+        }
+
+        let value = ((value - self.trigger_deadzone) / (1.0 - self.trigger_deadzone)).min(1.0);
+        (value * TRIGGER_RESOLUTION).round() as u8
+    }
+}
+
+// Local player devices resource, players without a device read the default device of their
+// handle
+
+#[derive(Clone, Debug, Default)]
+pub struct LocalInputDevicesRes(pub HashMap<PlayerHandle, InputDevice>);
+
+impl LocalInputDevicesRes {
+    // Players past the given devices get the default devices no other player was given, in order
+    pub fn from_local_handles(local_handles: &[PlayerHandle], devices: &[InputDevice]) -> Self {
+        let defaults = (0..)
+            .map(InputDevice::default_for)
+            .filter(|device| !devices.contains(device));
+
+        Self(
+            local_handles
+                .iter()
+                .copied()
+                .zip(devices.iter().copied().chain(defaults))
+                .collect(),
+        )
+    }
+}
+
+#[derive(SystemParam)]
+pub struct InputDevices<'w, 's> {
+    keyboard_input: Res<'w, Input<KeyCode>>,
+    mouse_input: Res<'w, Input<MouseButton>>,
+    gamepad_input: Res<'w, Input<GamepadButton>>,
+    gamepad_axes: Res<'w, Axis<GamepadAxis>>,
+    gamepad_button_axes: Res<'w, Axis<GamepadButton>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
 }
 
-pub fn game_input_system(_: In<PlayerHandle>, keyboard_input: Res<Input<KeyCode>>) -> GameInput {
-    let mut mask: u8 = 0;
+impl<'w, 's> InputDevices<'w, 's> {
+    fn keyboard_pressed(&self, binding: &InputBinding) -> bool {
+        match binding {
+            InputBinding::Key(key) => self.keyboard_input.pressed(*key),
+            InputBinding::MouseButton(button) => self.mouse_input.pressed(*button),
+            InputBinding::GamepadButton(_) => false,
+        }
+    }
 
-    if keyboard_input.pressed(KeyCode::Up) {
-        mask |= INPUT_UP;
+    fn gamepad_pressed(&self, gamepad: Gamepad, binding: &InputBinding) -> bool {
+        match binding {
+            InputBinding::GamepadButton(button_type) => self
+                .gamepad_input
+                .pressed(GamepadButton(gamepad, *button_type)),
+            _ => false,
+        }
     }
-    if keyboard_input.pressed(KeyCode::Down) {
-        mask |= INPUT_DOWN;
+
+    fn gamepad_analog(
+        &self,
+        gamepad: Gamepad,
+        input_map: &InputMapRes,
+        game_input: &mut GameInput,
+    ) {
+        let axis = |axis_type| {
+            self.gamepad_axes
+                .get(GamepadAxis(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+        let button_axis = |button_type| {
+            self.gamepad_button_axes
+                .get(GamepadButton(gamepad, button_type))
+                .unwrap_or(0.0)
+        };
+
+        (game_input.stick_x, game_input.stick_y) = input_map.quantize_stick(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        game_input.left_trigger =
+            input_map.quantize_trigger(button_axis(GamepadButtonType::LeftTrigger2));
+        game_input.right_trigger =
+            input_map.quantize_trigger(button_axis(GamepadButtonType::RightTrigger2));
     }
-    if keyboard_input.pressed(KeyCode::Left) {
-        mask |= INPUT_LEFT;
+}
+
+pub fn game_input_system(
+    In(player_handle): In<PlayerHandle>,
+    input_map: Res<InputMapRes>,
+    local_devices: Res<LocalInputDevicesRes>,
+    devices: InputDevices,
+) -> GameInput {
+    let mut game_input = GameInput::default();
+
+    let device = local_devices
+        .0
+        .get(&player_handle)
+        .copied()
+        .unwrap_or_else(|| InputDevice::default_for(player_handle));
+
+    match device {
+        InputDevice::KeyboardA => {
+            game_input.mask = input_map
+                .keyboard_a
+                .mask(|binding| devices.keyboard_pressed(binding));
+        }
+        InputDevice::KeyboardB => {
+            game_input.mask = input_map
+                .keyboard_b
+                .mask(|binding| devices.keyboard_pressed(binding));
+        }
+        InputDevice::Gamepad(id) => {
+            let gamepad = Gamepad(id);
+
+            game_input.mask = input_map
+                .gamepad
+                .mask(|binding| devices.gamepad_pressed(gamepad, binding));
+            devices.gamepad_analog(gamepad, &input_map, &mut game_input);
+        }
     }
-    if keyboard_input.pressed(KeyCode::Right) {
-        mask |= INPUT_RIGHT;
+
+    game_input
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::game::input::{
+        InputAction, InputBinding, InputDevice, InputMapRes, LocalInputDevicesRes,
+    };
+
+    #[test]
+    fn mask() {
+        let mut input_map = InputMapRes::default();
+        input_map
+            .keyboard_a
+            .rebind(InputAction::Left, InputBinding::Key(KeyCode::Q));
+        input_map.keyboard_a.bind(
+            InputAction::Right,
+            InputBinding::MouseButton(MouseButton::Left),
+        );
+
+        let pressed = [
+            InputBinding::Key(KeyCode::Left),
+            InputBinding::Key(KeyCode::Q),
+            InputBinding::Key(KeyCode::Up),
+            InputBinding::MouseButton(MouseButton::Left),
+        ];
+        let mask = input_map
+            .keyboard_a
+            .mask(|binding| pressed.contains(binding));
+
+        assert!(
+            mask == InputAction::Up.mask() | InputAction::Left.mask() | InputAction::Right.mask()
+        );
+        assert!(
+            input_map
+                .keyboard_b
+                .mask(|binding| pressed.contains(binding))
+                == 0
+        );
     }
 
-    GameInput { mask }
+    #[test]
+    fn quantization() {
+        let input_map = InputMapRes::default();
+
+        assert!(input_map.quantize_stick(0.1, -0.1) == (0, 0));
+        assert!(input_map.quantize_stick(1.0, 0.0) == (127, 0));
+        assert!(input_map.quantize_stick(0.0, -2.0) == (0, -127));
+        assert!(input_map.quantize_stick(0.6, 0.0) == (64, 0));
+        assert!(input_map.quantize_trigger(0.05) == 0);
+        assert!(input_map.quantize_trigger(1.0) == 255);
+    }
+
+    #[test]
+    fn devices() {
+        assert!("keyboard-b".parse::<InputDevice>() == Ok(InputDevice::KeyboardB));
+        assert!("gamepad-2".parse::<InputDevice>() == Ok(InputDevice::Gamepad(2)));
+        assert!("gamepad".parse::<InputDevice>().is_err());
+
+        let local_devices = LocalInputDevicesRes::from_local_handles(&[0, 1, 2, 3], &[]);
+        assert!(local_devices.0[&0] == InputDevice::KeyboardA);
+        assert!(local_devices.0[&1] == InputDevice::KeyboardB);
+        assert!(local_devices.0[&2] == InputDevice::Gamepad(0));
+        assert!(local_devices.0[&3] == InputDevice::Gamepad(1));
+
+        // Defaults skip the devices given to other players
+        let local_devices = LocalInputDevicesRes::from_local_handles(
+            &[1, 3, 4],
+            &[InputDevice::Gamepad(0), InputDevice::KeyboardA],
+        );
+        assert!(local_devices.0[&1] == InputDevice::Gamepad(0));
+        assert!(local_devices.0[&3] == InputDevice::KeyboardA);
+        assert!(local_devices.0[&4] == InputDevice::KeyboardB);
+    }
+
+    #[test]
+    fn serialization() {
+        let input_map = InputMapRes::default();
+        let serialized = ron::to_string(&input_map).unwrap();
+
+        assert!(ron::from_str::<InputMapRes>(&serialized).unwrap() == input_map);
+    }
+
+    #[test]
+    fn loading() {
+        let path = std::env::temp_dir().join(format!("input_map_{}.ron", std::process::id()));
+        std::fs::write(&path, "(bindigns: {})").unwrap();
+        let invalid = InputMapRes::load_or_default(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(invalid.unwrap_err().to_string().contains("bindigns"));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(InputMapRes::default().validate().is_ok());
+
+        let error = ron::from_str::<InputMapRes>("(stick_deadzone: 1.0)").unwrap_err();
+        assert!(error.to_string().contains("stick_deadzone"));
+
+        let input_map = InputMapRes {
+            trigger_deadzone: f32::NAN,
+            ..Default::default()
+        };
+        assert!(input_map.validate().unwrap_err().field == "trigger_deadzone");
+    }
 }
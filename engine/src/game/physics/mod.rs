@@ -1,4 +1,5 @@
 pub mod input;
+pub mod motion;
 
 use bevy::input::system::exit_on_esc_system;
 use bevy::prelude::*;
@@ -6,18 +7,24 @@ use bevy_ggrs::{Rollback, RollbackIdProvider};
 use derive_more::{Deref, DerefMut};
 use ggrs::{InputStatus, P2PSession, SpectatorSession, SyncTestSession};
 
-use crate::core::maths::Vector2;
+use crate::core::maths::{OverflowContext, Vector2};
 use crate::core::physics::*;
-use crate::core::transform::Transform2;
+use crate::core::transform::{Transform2, Transform2Bundle, TransformSmoothing};
 use crate::core::EngineGGRSConfig;
 use crate::game::input::*;
+use crate::game::motion::{motion_leniency_startup_system, InputHistoryRes, MotionLeniencyRes};
 
 pub trait GameApp {
     fn insert_game(&mut self) -> &mut Self;
 }
 impl GameApp for App {
     fn insert_game(&mut self) -> &mut Self {
-        self.add_startup_system(player_startup_system)
+        self.init_resource::<InputMapRes>()
+            .init_resource::<LocalInputDevicesRes>()
+            .init_resource::<InputHistoryRes>()
+            .init_resource::<MotionLeniencyRes>()
+            .add_startup_system(motion_leniency_startup_system)
+            .add_startup_system(player_startup_system)
     }
 }
 
@@ -49,11 +56,14 @@ fn player_startup_system(
         .expect("No GGRS session found");
 
     for handle in 0..num_players {
+        let transform2 = Transform2::from_pos(Vector2::new(handle * 40, 0));
+
         commands
             .spawn()
             .insert(Player { handle })
             .insert(Rollback::new(rollback_id_provider.next_id()))
-            .insert(Transform2::from_pos(Vector2::new(handle * 40, 0)))
+            .insert_bundle(Transform2Bundle::from(transform2))
+            .insert(TransformSmoothing::default())
             .insert_bundle(SpriteBundle {
                 sprite: Sprite {
                     color: Color::rgb(1.0, 0.27, 0.0),
@@ -76,25 +86,27 @@ fn player_startup_system(
 }
 
 pub fn move_player_system(
-    mut query: Query<(&Player, &mut KinematicBody), With<Rollback>>,
+    mut query: Query<(Entity, &Player, &mut KinematicBody), With<Rollback>>,
     inputs: Res<Vec<(GameInput, InputStatus)>>,
 ) {
-    for (player, mut kinematic_body) in query.iter_mut() {
-        let input_mask = inputs[player.handle as usize].0.mask;
+    for (entity, player, mut kinematic_body) in query.iter_mut() {
+        let _overflow_context = OverflowContext::enter("move_player_system", Some(entity));
+
+        let input = inputs[player.handle].0;
 
         kinematic_body.velocity.x = 0.into();
         kinematic_body.velocity.y = 0.into();
-        if input_mask & INPUT_UP != 0 {
-            kinematic_body.velocity.y += 2.into();
+        if input.pressed(InputAction::Up) {
+            kinematic_body.velocity.y += 120.into();
         }
-        if input_mask & INPUT_DOWN != 0 {
-            kinematic_body.velocity.y += (-2).into();
+        if input.pressed(InputAction::Down) {
+            kinematic_body.velocity.y += (-120).into();
         }
-        if input_mask & INPUT_LEFT != 0 {
-            kinematic_body.velocity.x += (-2).into();
+        if input.pressed(InputAction::Left) {
+            kinematic_body.velocity.x += (-120).into();
         }
-        if input_mask & INPUT_RIGHT != 0 {
-            kinematic_body.velocity.x += 2.into();
+        if input.pressed(InputAction::Right) {
+            kinematic_body.velocity.x += 120.into();
         }
     }
 }
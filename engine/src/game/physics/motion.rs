@@ -0,0 +1,551 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::{Component, Res, ResMut};
+use bevy::reflect::{impl_reflect_value, ReflectDeserialize};
+use ggrs::{InputStatus, PlayerHandle};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+
+use crate::config::ConfigError;
+use crate::game::input::{GameInput, InputAction};
+
+pub const INPUT_HISTORY_FRAMES: usize = 60;
+
+// Stick deflection from which it counts as a direction, half of the quantized range
+const STICK_DIRECTION_THRESHOLD: i8 = 64;
+
+// Directions in numpad notation, relative to the side the player is facing
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Direction {
+    DownBack = 1,
+    Down = 2,
+    DownForward = 3,
+    Back = 4,
+    Neutral = 5,
+    Forward = 6,
+    UpBack = 7,
+    Up = 8,
+    UpForward = 9,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Facing {
+    Left,
+    Right,
+}
+
+impl Direction {
+    pub fn from_input(input: &GameInput, facing: Facing) -> Self {
+        let axis = |negative: bool, positive: bool, stick: i8| {
+            if positive && !negative || stick >= STICK_DIRECTION_THRESHOLD {
+                1
+            } else if negative && !positive || stick <= -STICK_DIRECTION_THRESHOLD {
+                -1
+            } else {
+                0
+            }
+        };
+
+        let mut x = axis(
+            input.pressed(InputAction::Left),
+            input.pressed(InputAction::Right),
+            input.stick_x,
+        );
+        let y = axis(
+            input.pressed(InputAction::Down),
+            input.pressed(InputAction::Up),
+            input.stick_y,
+        );
+
+        if facing == Facing::Left {
+            x = -x;
+        }
+
+        match (x, y) {
+            (-1, -1) => Direction::DownBack,
+            (0, -1) => Direction::Down,
+            (1, -1) => Direction::DownForward,
+            (-1, 0) => Direction::Back,
+            (1, 0) => Direction::Forward,
+            (-1, 1) => Direction::UpBack,
+            (0, 1) => Direction::Up,
+            (1, 1) => Direction::UpForward,
+            _ => Direction::Neutral,
+        }
+    }
+}
+
+// Motions
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Motion {
+    // 236
+    QuarterCircleForward,
+    // 214
+    QuarterCircleBack,
+    // 623
+    DragonPunch,
+    // [4]6
+    ChargeBackForward,
+    // [2]8
+    ChargeDownUp,
+    // 656
+    DoubleTapForward,
+    // 454
+    DoubleTapBack,
+}
+
+const BACK: &[Direction] = &[Direction::DownBack, Direction::Back, Direction::UpBack];
+const DOWN: &[Direction] = &[Direction::DownBack, Direction::Down, Direction::DownForward];
+const UP: &[Direction] = &[Direction::UpBack, Direction::Up, Direction::UpForward];
+// Each step accepts any of its directions
+enum MotionSteps {
+    Sequence(&'static [&'static [Direction]]),
+    Charge {
+        hold: &'static [Direction],
+        release: &'static [Direction],
+    },
+    DoubleTap(Direction),
+}
+
+impl Motion {
+    fn steps(self) -> MotionSteps {
+        match self {
+            Motion::QuarterCircleForward => MotionSteps::Sequence(&[
+                &[Direction::Down],
+                &[Direction::DownForward],
+                &[Direction::Forward],
+            ]),
+            Motion::QuarterCircleBack => MotionSteps::Sequence(&[
+                &[Direction::Down],
+                &[Direction::DownBack],
+                &[Direction::Back],
+            ]),
+            Motion::DragonPunch => MotionSteps::Sequence(&[
+                &[Direction::Forward],
+                &[Direction::Down],
+                &[Direction::DownForward],
+            ]),
+            Motion::ChargeBackForward => MotionSteps::Charge {
+                hold: BACK,
+                release: &[Direction::Forward],
+            },
+            Motion::ChargeDownUp => MotionSteps::Charge {
+                hold: DOWN,
+                release: UP,
+            },
+            Motion::DoubleTapForward => MotionSteps::DoubleTap(Direction::Forward),
+            Motion::DoubleTapBack => MotionSteps::DoubleTap(Direction::Back),
+        }
+    }
+}
+
+// Motion leniency resource
+
+#[derive(Clone, Debug)]
+pub struct MotionLeniencyRes {
+    // Frames allowed between two steps of a motion, the two presses of a double tap are steps too
+    pub step_frames: usize,
+    // Frames a charge direction must be held before its release
+    pub charge_frames: usize,
+}
+
+impl Default for MotionLeniencyRes {
+    fn default() -> Self {
+        Self {
+            step_frames: 10,
+            charge_frames: 30,
+        }
+    }
+}
+
+impl MotionLeniencyRes {
+    // A charge is released on the frame after it was held, so the history must hold both
+    pub fn validate(&self, history_capacity: usize) -> Result<(), ConfigError> {
+        if self.charge_frames >= history_capacity {
+            return Err(ConfigError::new(
+                "charge_frames",
+                format!(
+                    "must be less than the input history capacity of {} frames",
+                    history_capacity
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Input history resource, restored on rollback so that resimulated frames see the inputs they
+// were first simulated with
+
+#[derive(Clone, Debug, Hash, Component, Serialize, Deserialize)]
+pub struct InputHistoryRes {
+    capacity: usize,
+    // Newest input first for every player handle
+    players: Vec<VecDeque<GameInput>>,
+}
+
+impl_reflect_value!(InputHistoryRes(Hash, Serialize, Deserialize));
+
+impl Default for InputHistoryRes {
+    fn default() -> Self {
+        Self::with_capacity(INPUT_HISTORY_FRAMES)
+    }
+}
+
+impl InputHistoryRes {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            capacity,
+            players: Vec::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn push(&mut self, inputs: impl IntoIterator<Item = GameInput>) {
+        for (player_handle, input) in inputs.into_iter().enumerate() {
+            if player_handle >= self.players.len() {
+                self.players.resize_with(player_handle + 1, VecDeque::new);
+            }
+
+            let history = &mut self.players[player_handle];
+            history.push_front(input);
+            history.truncate(self.capacity);
+        }
+    }
+
+    // Returns the inputs of the player, newest first
+    pub fn inputs(&self, player_handle: PlayerHandle) -> impl Iterator<Item = &GameInput> {
+        self.players.get(player_handle).into_iter().flatten()
+    }
+
+    // Returns the directions of the player, newest first
+    pub fn directions(&self, player_handle: PlayerHandle, facing: Facing) -> Vec<Direction> {
+        self.inputs(player_handle)
+            .map(|input| Direction::from_input(input, facing))
+            .collect()
+    }
+
+    // Returns true only on the frame where the motion is completed, holding its last direction
+    // does not perform it again
+    pub fn performed(
+        &self,
+        player_handle: PlayerHandle,
+        motion: Motion,
+        facing: Facing,
+        leniency: &MotionLeniencyRes,
+    ) -> bool {
+        let directions = self.directions(player_handle, facing);
+
+        if directions.is_empty() {
+            return false;
+        }
+
+        match motion.steps() {
+            MotionSteps::Sequence(steps) => {
+                match_sequence(&directions, steps, None, leniency.step_frames)
+            }
+            MotionSteps::Charge { hold, release } => {
+                match_charge(&directions, hold, release, leniency)
+            }
+            MotionSteps::DoubleTap(direction) => {
+                match_double_tap(&directions, direction, leniency.step_frames)
+            }
+        }
+    }
+}
+
+// Returns true when one of the directions is entered at the frame, directions are newest first
+fn entered(directions: &[Direction], frame: usize, accepted: &[Direction]) -> bool {
+    accepted.contains(&directions[frame])
+        && directions
+            .get(frame + 1)
+            .is_none_or(|previous| !accepted.contains(previous))
+}
+
+// Matches the steps from the last one, entered on the current frame, each other one must be
+// found within step_frames frames before the frame where the following one was found
+fn match_sequence(
+    directions: &[Direction],
+    steps: &[&[Direction]],
+    next_frame: Option<usize>,
+    step_frames: usize,
+) -> bool {
+    let (step, previous_steps) = match steps.split_last() {
+        Some(split) => split,
+        None => return true,
+    };
+
+    let next_frame = match next_frame {
+        Some(next_frame) => next_frame,
+        None => {
+            return entered(directions, 0, step)
+                && match_sequence(directions, previous_steps, Some(0), step_frames)
+        }
+    };
+
+    (next_frame + 1..=(next_frame + step_frames).min(directions.len().saturating_sub(1))).any(
+        |frame| {
+            step.contains(&directions[frame])
+                && match_sequence(directions, previous_steps, Some(frame), step_frames)
+        },
+    )
+}
+
+fn match_charge(
+    directions: &[Direction],
+    hold: &[Direction],
+    release: &[Direction],
+    leniency: &MotionLeniencyRes,
+) -> bool {
+    if !entered(directions, 0, release) {
+        return false;
+    }
+
+    (1..=leniency.step_frames.min(directions.len() - 1)).any(|frame| {
+        directions[frame..]
+            .iter()
+            .take_while(|direction| hold.contains(direction))
+            .count()
+            >= leniency.charge_frames
+    })
+}
+
+// Matches two presses of the direction, the second one on the current frame and at most
+// step_frames frames after the first one
+fn match_double_tap(directions: &[Direction], direction: Direction, step_frames: usize) -> bool {
+    let direction = &[direction];
+
+    entered(directions, 0, direction)
+        && (1..=step_frames.min(directions.len().saturating_sub(1)))
+            .any(|frame| entered(directions, frame, direction))
+}
+
+// Motion query for game systems, the history is up to date for the whole Game stage
+
+#[derive(SystemParam)]
+pub struct Motions<'w, 's> {
+    history: Res<'w, InputHistoryRes>,
+    leniency: Res<'w, MotionLeniencyRes>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Motions<'w, 's> {
+    pub fn performed(&self, player_handle: PlayerHandle, motion: Motion, facing: Facing) -> bool {
+        self.history
+            .performed(player_handle, motion, facing, &self.leniency)
+    }
+
+    pub fn history(&self) -> &InputHistoryRes {
+        &self.history
+    }
+}
+
+// Input history systems
+
+pub fn input_history_system(
+    mut history: ResMut<InputHistoryRes>,
+    inputs: Res<Vec<(GameInput, InputStatus)>>,
+) {
+    history.push(inputs.iter().map(|(input, _)| *input));
+}
+
+// A charge longer than the history could never be performed
+pub fn motion_leniency_startup_system(
+    leniency: Res<MotionLeniencyRes>,
+    history: Res<InputHistoryRes>,
+) {
+    if let Err(error) = leniency.validate(history.capacity()) {
+        panic!("{}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::input::{GameInput, InputAction};
+    use crate::game::motion::{Facing, InputHistoryRes, Motion, MotionLeniencyRes};
+
+    fn input(actions: &[InputAction]) -> GameInput {
+        GameInput {
+            mask: actions.iter().fold(0, |mask, action| mask | action.mask()),
+            ..Default::default()
+        }
+    }
+
+    // Pushes each frame for player 0, oldest first
+    fn history(frames: &[&[InputAction]]) -> InputHistoryRes {
+        let mut history = InputHistoryRes::default();
+        for actions in frames {
+            history.push([input(actions)]);
+        }
+        history
+    }
+
+    fn performed(history: &InputHistoryRes, motion: Motion, facing: Facing) -> bool {
+        history.performed(0, motion, facing, &MotionLeniencyRes::default())
+    }
+
+    #[test]
+    fn sequences() {
+        use InputAction::*;
+
+        let quarter_circle = history(&[&[], &[Down], &[Down, Right], &[Right]]);
+
+        assert!(performed(
+            &quarter_circle,
+            Motion::QuarterCircleForward,
+            Facing::Right
+        ));
+        assert!(!performed(
+            &quarter_circle,
+            Motion::QuarterCircleForward,
+            Facing::Left
+        ));
+        assert!(!performed(
+            &quarter_circle,
+            Motion::DragonPunch,
+            Facing::Right
+        ));
+
+        let dragon_punch = history(&[&[Left], &[Down], &[Down, Left]]);
+        assert!(performed(&dragon_punch, Motion::DragonPunch, Facing::Left));
+    }
+
+    #[test]
+    fn leniency() {
+        use InputAction::*;
+
+        let mut frames: Vec<&[InputAction]> = vec![&[Right], &[], &[Right]];
+        assert!(performed(
+            &history(&frames),
+            Motion::DoubleTapForward,
+            Facing::Right
+        ));
+
+        // The motion is only performed on the frame that completes it, even when held
+        frames.extend(std::iter::repeat_n::<&[InputAction]>(&[Right], 10));
+        frames.extend(std::iter::repeat_n::<&[InputAction]>(&[], 10));
+        let performed_frames = (1..=frames.len())
+            .filter(|end| {
+                performed(
+                    &history(&frames[..*end]),
+                    Motion::DoubleTapForward,
+                    Facing::Right,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert!(performed_frames == [3]);
+
+        // Performed again by a new motion right after the previous one
+        let quarter_circles: Vec<&[InputAction]> = vec![
+            &[Down],
+            &[Down, Right],
+            &[Right],
+            &[Down],
+            &[Down, Right],
+            &[Right],
+        ];
+        let performed_frames = (1..=quarter_circles.len())
+            .filter(|end| {
+                performed(
+                    &history(&quarter_circles[..*end]),
+                    Motion::QuarterCircleForward,
+                    Facing::Right,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert!(performed_frames == [3, 6]);
+
+        // Too slow between two steps
+        let mut frames: Vec<&[InputAction]> = vec![&[Right]];
+        frames.extend(std::iter::repeat_n::<&[InputAction]>(&[], 11));
+        frames.push(&[Right]);
+        assert!(!performed(
+            &history(&frames),
+            Motion::DoubleTapForward,
+            Facing::Right
+        ));
+    }
+
+    #[test]
+    fn charges() {
+        use InputAction::*;
+
+        let mut frames: Vec<&[InputAction]> = vec![&[Left]; 30];
+        frames.push(&[Right]);
+        assert!(performed(
+            &history(&frames),
+            Motion::ChargeBackForward,
+            Facing::Right
+        ));
+        assert!(!performed(
+            &history(&frames),
+            Motion::ChargeDownUp,
+            Facing::Right
+        ));
+
+        let mut frames: Vec<&[InputAction]> = vec![&[Left]; 29];
+        frames.push(&[Right]);
+        assert!(!performed(
+            &history(&frames),
+            Motion::ChargeBackForward,
+            Facing::Right
+        ));
+
+        // The charge may end up to step_frames frames before the release
+        let mut frames: Vec<&[InputAction]> = vec![&[Left]; 30];
+        frames.extend([&[] as &[InputAction]; 9]);
+        frames.push(&[Right]);
+        assert!(performed(
+            &history(&frames),
+            Motion::ChargeBackForward,
+            Facing::Right
+        ));
+
+        frames.insert(30, &[]);
+        assert!(!performed(
+            &history(&frames),
+            Motion::ChargeBackForward,
+            Facing::Right
+        ));
+    }
+
+    #[test]
+    fn leniency_validation() {
+        let leniency = MotionLeniencyRes::default();
+        assert!(leniency
+            .validate(InputHistoryRes::default().capacity())
+            .is_ok());
+
+        let leniency = MotionLeniencyRes {
+            charge_frames: 60,
+            ..Default::default()
+        };
+        assert!(leniency.validate(60).unwrap_err().field == "charge_frames");
+        assert!(leniency.validate(61).is_ok());
+    }
+
+    #[test]
+    fn capacity() {
+        let mut history = InputHistoryRes::with_capacity(4);
+        for _ in 0..10 {
+            history.push([GameInput::default(), GameInput::default()]);
+        }
+
+        assert!(history.inputs(0).count() == 4);
+        assert!(history.inputs(1).count() == 4);
+        assert!(history.inputs(2).count() == 0);
+        assert!(!history.performed(
+            2,
+            Motion::DragonPunch,
+            Facing::Right,
+            &MotionLeniencyRes::default()
+        ));
+    }
+}
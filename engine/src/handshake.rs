@@ -0,0 +1,230 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+// Every peer must seed the rollback rng identically, ggrs has no way to agree on it so the seed is
+// negotiated over the session socket before the session starts.
+
+const SEED_MAGIC: &[u8; 4] = b"SEED";
+const SEED_PACKET_SIZE: usize = 13;
+const SEED_RECEIVED_FLAG: u8 = 1;
+const SEED_FIXED_FLAG: u8 = 2;
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+// Random bits from the randomly keyed std hasher, which is seeded by the operating system
+pub fn random_seed() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct SeedPacket {
+    bits: u64,
+    // The bits are a seed given to every peer rather than their share of the seed
+    fixed: bool,
+    // The sender already received ours
+    received: bool,
+}
+
+impl SeedPacket {
+    fn to_bytes(self) -> [u8; SEED_PACKET_SIZE] {
+        let mut packet = [0; SEED_PACKET_SIZE];
+        packet[..4].copy_from_slice(SEED_MAGIC);
+        packet[4..12].copy_from_slice(&self.bits.to_le_bytes());
+        if self.received {
+            packet[12] |= SEED_RECEIVED_FLAG;
+        }
+        if self.fixed {
+            packet[12] |= SEED_FIXED_FLAG;
+        }
+        packet
+    }
+
+    // None for any other packet
+    fn from_bytes(packet: &[u8]) -> Option<Self> {
+        if packet.len() != SEED_PACKET_SIZE || &packet[..4] != SEED_MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            bits: u64::from_le_bytes(packet[4..12].try_into().ok()?),
+            fixed: packet[12] & SEED_FIXED_FLAG != 0,
+            received: packet[12] & SEED_RECEIVED_FLAG != 0,
+        })
+    }
+}
+
+// Blocks until every remote confirmed having received our share of the seed, and returns the XOR
+// of the shares of every peer. A fixed seed, for replays and tests, must be given to every peer.
+// A remote that already started its ggrs session only sends ggrs packets, which confirms it too.
+pub fn negotiate_seed(
+    socket: &UdpSocket,
+    remotes: &[SocketAddr],
+    fixed_seed: Option<u64>,
+    timeout: Duration,
+) -> Result<u64, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    let bits = fixed_seed.unwrap_or_else(random_seed);
+    let fixed = fixed_seed.is_some();
+    let mut received = HashMap::new();
+    let mut confirmed = HashSet::new();
+    let mut buffer = [0; 1024];
+
+    socket.set_read_timeout(Some(RESEND_INTERVAL))?;
+
+    while confirmed.len() < remotes.len() {
+        if Instant::now() >= deadline {
+            let missing = remotes
+                .iter()
+                .filter(|remote| !confirmed.contains(*remote))
+                .map(|remote| remote.to_string())
+                .collect::<Vec<_>>();
+            return Err(format!(
+                "Seed handshake timed out waiting for {}",
+                missing.join(", ")
+            )
+            .into());
+        }
+
+        for remote in remotes.iter().filter(|remote| !confirmed.contains(*remote)) {
+            let packet = SeedPacket {
+                bits,
+                fixed,
+                received: received.contains_key(remote),
+            };
+            socket.send_to(&packet.to_bytes(), remote)?;
+        }
+
+        let send_time = Instant::now();
+        while send_time.elapsed() < RESEND_INTERVAL {
+            let (size, address) = match socket.recv_from(&mut buffer) {
+                Ok(packet) => packet,
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    break
+                }
+                // Windows reports unreachable remotes as connection resets
+                Err(error) if error.kind() == ErrorKind::ConnectionReset => continue,
+                Err(error) => return Err(error.into()),
+            };
+            if !remotes.contains(&address) {
+                continue;
+            }
+
+            match SeedPacket::from_bytes(&buffer[..size]) {
+                Some(packet) if packet.fixed != fixed => {
+                    return Err(format!(
+                        "Seed mismatch with {}: only one of the peers was given a fixed seed",
+                        address
+                    )
+                    .into());
+                }
+                Some(packet) if fixed && packet.bits != bits => {
+                    return Err(format!(
+                        "Seed mismatch with {}: {} here, {} there",
+                        address, bits, packet.bits
+                    )
+                    .into());
+                }
+                Some(packet) => {
+                    if received.insert(address, packet.bits).is_none() {
+                        let reply = SeedPacket {
+                            bits,
+                            fixed,
+                            received: true,
+                        };
+                        socket.send_to(&reply.to_bytes(), address)?;
+                    }
+                    if packet.received {
+                        confirmed.insert(address);
+                    }
+                }
+                None => {
+                    if received.contains_key(&address) {
+                        confirmed.insert(address);
+                    }
+                }
+            }
+        }
+    }
+
+    if fixed {
+        Ok(bits)
+    } else {
+        Ok(received.values().fold(bits, |seed, bits| seed ^ bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::handshake::negotiate_seed;
+
+    fn negotiate(fixed_seeds: [Option<u64>; 2]) -> [Option<u64>; 2] {
+        let sockets = [
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        ];
+        let addresses = [
+            sockets[0].local_addr().unwrap(),
+            sockets[1].local_addr().unwrap(),
+        ];
+        let [first, second] = sockets;
+
+        let remote = thread::spawn(move || {
+            negotiate_seed(
+                &second,
+                &[addresses[0]],
+                fixed_seeds[1],
+                Duration::from_secs(5),
+            )
+            .ok()
+        });
+        let local = negotiate_seed(
+            &first,
+            &[addresses[1]],
+            fixed_seeds[0],
+            Duration::from_secs(5),
+        )
+        .ok();
+
+        [local, remote.join().unwrap()]
+    }
+
+    #[test]
+    fn random_seeds() {
+        let [first, second] = negotiate([None, None]);
+        assert!(first.is_some() && first == second);
+
+        // Every session gets its own seed
+        assert!(negotiate([None, None])[0] != first);
+    }
+
+    #[test]
+    fn fixed_seeds() {
+        assert!(negotiate([Some(42), Some(42)]) == [Some(42), Some(42)]);
+        assert!(negotiate([Some(42), Some(7)]) == [None, None]);
+        assert!(negotiate([Some(42), None]) == [None, None]);
+    }
+
+    #[test]
+    fn missing_remote() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let result = negotiate_seed(
+            &socket,
+            &[silent.local_addr().unwrap()],
+            Some(42),
+            Duration::from_millis(300),
+        );
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+    }
+}
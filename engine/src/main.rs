@@ -1,63 +1,182 @@
+pub mod config;
 pub mod core;
 pub mod game;
+pub mod handshake;
 
 use bevy::prelude::*;
 use bevy_ggrs::SessionType;
-use ggrs::{P2PSession, PlayerType, SessionBuilder, UdpNonBlockingSocket};
+use ggrs::{P2PSession, PlayerHandle, PlayerType, SessionBuilder, UdpNonBlockingSocket};
 use std::error::Error;
-use std::net::SocketAddr;
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::time::Duration;
 use structopt::StructOpt;
 
-use crate::core::{EngineApp, EngineConfig, EngineGGRSConfig};
+use crate::config::{NetworkConfig, SessionConfig, SESSION_CONFIG_PATH};
+use crate::core::{EngineApp, EngineGGRSConfig};
+use crate::game::input::{InputDevice, LocalInputDevicesRes};
 use crate::game::GameApp;
+use crate::handshake::{negotiate_seed, random_seed};
+
+const SEED_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(StructOpt)]
 struct CommandLineArgs {
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    // Every flag below overrides the session config
+    #[structopt(long)]
+    offline: bool,
     #[structopt(long)]
-    port: u16,
+    port: Option<u16>,
     #[structopt(long)]
     players: Vec<String>,
-    #[structopt(long, default_value = "2")]
-    input_delay: usize,
-    #[structopt(long, default_value = "12")]
-    max_prediction_window: usize,
+    #[structopt(long)]
+    input_delay: Option<usize>,
+    #[structopt(long)]
+    max_prediction_window: Option<usize>,
+    // Fixed rng seed for replays and tests, every peer must be given the same
+    #[structopt(long)]
+    seed: Option<u64>,
+    // Devices of the local players in handle order: keyboard-a, keyboard-b or gamepad-N
+    #[structopt(long)]
+    devices: Vec<InputDevice>,
+}
+
+impl CommandLineArgs {
+    fn apply(self, config: &mut SessionConfig) -> Result<(), Box<dyn Error>> {
+        let network = &mut config.network;
+
+        network.offline |= self.offline;
+        if self.port.is_some() {
+            network.port = self.port;
+        }
+        if !self.players.is_empty() {
+            network.players = self.players;
+        }
+        if let Some(input_delay) = self.input_delay {
+            network.input_delay = input_delay;
+        }
+        if let Some(max_prediction_window) = self.max_prediction_window {
+            network.max_prediction_window = max_prediction_window;
+        }
+        if let Some(seed) = self.seed {
+            config.engine.rng_seed = Some(seed);
+        }
+        if !self.devices.is_empty() {
+            let mut input_map = config.input_map()?;
+            input_map.local_devices = self.devices;
+            config.input = Some(input_map);
+        }
+
+        Ok(())
+    }
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let cmd = CommandLineArgs::from_args();
+    let mut config = match &cmd.config {
+        Some(path) => SessionConfig::load(path)?,
+        None => SessionConfig::load_or_default(SESSION_CONFIG_PATH)?,
+    };
+    cmd.apply(&mut config)?;
+    config.validate()?;
+
+    let mut app = App::new();
+
+    let local_handles = if config.network.offline {
+        config.engine.rng_seed = config.engine.rng_seed.or_else(|| Some(random_seed()));
+        start_offline_session(&config.network, &mut app)?
+    } else {
+        let (local_handles, rng_seed) =
+            start_p2p_session(&config.network, config.engine.rng_seed, &mut app)?;
+        config.engine.rng_seed = Some(rng_seed);
+        local_handles
+    };
+
+    let input_map = config.input_map()?;
+    let local_devices =
+        LocalInputDevicesRes::from_local_handles(&local_handles, &input_map.local_devices);
+
+    app.insert_engine(config.engine)
+        .insert_game()
+        .insert_resource(input_map)
+        .insert_resource(local_devices)
+        //
+        .run();
+
+    Ok(())
+}
+
+// Session
+
+// Returns the local handles and the negotiated rng seed
+fn start_p2p_session(
+    network: &NetworkConfig,
+    fixed_rng_seed: Option<u64>,
+    app: &mut App,
+) -> Result<(Vec<PlayerHandle>, u64), Box<dyn Error>> {
+    let port = network.port.ok_or("Invalid network.port: missing")?;
     let mut session_builder = SessionBuilder::<EngineGGRSConfig>::new()
-        .with_num_players(cmd.players.len())
-        .with_input_delay(cmd.input_delay)
+        .with_num_players(network.players.len())
+        .with_input_delay(network.input_delay)
         .with_sparse_saving_mode(true)
-        .with_max_prediction_window(cmd.max_prediction_window);
+        .with_max_prediction_window(network.max_prediction_window);
+
+    let mut local_handles = Vec::new();
+    let mut remote_addresses = Vec::new();
 
-    for (player_handle, player_address) in cmd.players.iter().enumerate() {
+    for (player_handle, player_address) in network.players.iter().enumerate() {
         if player_address == "local" {
             session_builder = session_builder.add_player(PlayerType::Local, player_handle)?;
+            local_handles.push(player_handle);
         } else {
-            let remote_player_address: SocketAddr = player_address
-                .parse()
-                .expect("Invalid remote player address");
+            let remote_player_address: SocketAddr = player_address.parse()?;
+            remote_addresses.push(remote_player_address);
             session_builder = session_builder
                 .add_player(PlayerType::Remote(remote_player_address), player_handle)?;
         }
     }
 
-    let socket = UdpNonBlockingSocket::bind_to_port(cmd.port)?;
+    // The handshake socket is closed before ggrs binds the same port, ggrs resends what is lost
+    let rng_seed = {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        negotiate_seed(
+            &socket,
+            &remote_addresses,
+            fixed_rng_seed,
+            SEED_HANDSHAKE_TIMEOUT,
+        )?
+    };
+
+    let socket = UdpNonBlockingSocket::bind_to_port(port)?;
     let session = session_builder.start_p2p_session(socket)?;
 
-    App::new()
-        .insert_engine(EngineConfig::default())
-        .insert_game()
-        //
-        .insert_resource(session)
-        .insert_resource(SessionType::P2PSession)
-        //
-        .add_system(print_events_system)
-        //
-        .run();
+    app.insert_resource(session)
+        .insert_resource(SessionType::P2PSession);
+    if network.print_events {
+        app.add_system(print_events_system);
+    }
 
-    Ok(())
+    Ok((local_handles, rng_seed))
+}
+
+// A sync test session with a check distance of 0 never saves nor rolls back, so it simply runs
+// the rollback schedule once per frame with the inputs of every local player
+fn start_offline_session(
+    network: &NetworkConfig,
+    app: &mut App,
+) -> Result<Vec<PlayerHandle>, Box<dyn Error>> {
+    let session = SessionBuilder::<EngineGGRSConfig>::new()
+        .with_num_players(network.players.len())
+        .with_input_delay(0)
+        .with_check_distance(0)
+        .start_synctest_session()?;
+
+    app.insert_resource(session)
+        .insert_resource(SessionType::SyncTestSession);
+
+    Ok((0..network.players.len()).collect())
 }
 
 fn print_events_system(mut session: ResMut<P2PSession<EngineGGRSConfig>>) {